@@ -44,6 +44,45 @@ defmodule ParserBench do
     Json.decode!(json)
   end
 
+  # Whitespace and string scanning
+
+  bench "Minified (jiffy)", [json: gen_minified] do
+    :jiffy.decode(json, [:return_maps])
+  end
+
+  bench "Minified (Json)", [json: gen_minified] do
+    Json.decode!(json)
+  end
+
+  bench "Long strings (jiffy)", [json: gen_long_strings] do
+    :jiffy.decode(json)
+  end
+
+  bench "Long strings (Json)", [json: gen_long_strings] do
+    Json.decode!(json)
+  end
+
+  bench "Indentation (jiffy)", [json: gen_indented] do
+    :jiffy.decode(json)
+  end
+
+  bench "Indentation (Json)", [json: gen_indented] do
+    Json.decode!(json)
+  end
+
+  defp gen_minified do
+    gen_json() |> String.split("\n") |> Enum.map(&String.trim_leading/1) |> Enum.join
+  end
+
+  defp gen_long_strings do
+    string = ~s(") <> String.duplicate("x", 4096) <> ~s(")
+    "[" <> Enum.join(List.duplicate(string, 64), ",") <> "]"
+  end
+
+  defp gen_indented do
+    "[" <> Enum.join(List.duplicate("\n" <> String.duplicate(" ", 64) <> "1", 4096), ",") <> "]"
+  end
+
   defp gen_utf8 do
     text = File.read!(Path.expand("data/UTF-8-demo.txt", __DIR__))
     Poison.encode!(text) |> IO.iodata_to_binary
@@ -1,6 +1,39 @@
 defmodule Json do
   defmodule Error do
-    defexception [:message]
+    @moduledoc """
+    Raised by the bang functions. Decode errors carry the same `:reason`,
+    `:token`, `:position`, `:line` and `:column` as the error maps returned
+    by the non-raising functions.
+    """
+    defexception [:message, :reason, :token, :position, :line, :column]
+
+    @impl true
+    def exception(%{reason: _} = details), do: struct(__MODULE__, details)
+    def exception(reason) when is_atom(reason), do: %__MODULE__{reason: reason}
+    def exception(opts), do: super(opts)
+
+    @impl true
+    def message(%{message: nil, position: nil} = error), do: describe(error)
+    def message(%{message: nil} = error), do: "#{describe(error)} at position #{error.position}"
+    def message(%{message: message}), do: message
+
+    defp describe(%{reason: :unexpected_end}), do: "Unexpected end of JSON input"
+    defp describe(%{reason: :unexpected_token, token: t}), do: "Unexpected token #{t} in JSON"
+    defp describe(%{reason: :invalid_number, token: t}), do: "Unexpected number #{t} in JSON"
+    defp describe(%{reason: :invalid_escape}), do: "Bad Unicode escape in JSON"
+    defp describe(%{reason: :invalid_utf8}), do: "Invalid UTF-8 in JSON"
+    defp describe(%{reason: :lone_surrogate, token: t}), do: "Lone surrogate #{t} in JSON"
+    defp describe(%{reason: :control_character}), do: "Unexpected control character in JSON"
+    defp describe(%{reason: :max_depth_exceeded}), do: "Maximum nesting depth exceeded"
+    defp describe(%{reason: :input_too_large}), do: "JSON input too large"
+    defp describe(%{reason: :string_too_long}), do: "String too long in JSON"
+    defp describe(%{reason: :too_many_elements}), do: "Too many elements in JSON"
+    defp describe(%{reason: :number_too_long}), do: "Number too long in JSON"
+    defp describe(%{reason: :non_finite_number, token: t}), do: "Non-finite number #{t} in JSON"
+    defp describe(%{reason: :unknown_atom, token: t}), do: "Key #{inspect(t)} is not an existing atom"
+    defp describe(%{reason: :invalid_atom, token: t}), do: "Key #{inspect(t)} cannot be an atom"
+    defp describe(%{reason: :duplicate_key, token: t}), do: "Duplicate key #{inspect(t)} in JSON"
+    defp describe(%{reason: reason}), do: "#{reason}"
   end
 
   alias Json.Native
@@ -8,6 +41,94 @@ defmodule Json do
   @doc ~S"""
   Decodes a binary string into Elixir terms.
 
+  Every decode function runs the same parser, so the same options are
+  accepted by all of them and behave the same way regardless of how the
+  work is scheduled. Unknown options raise an `ArgumentError`.
+
+  Invalid documents return `{:error, details}`, where `details` is a map
+  with the `:reason` (such as `:unexpected_token` or `:unexpected_end`), the
+  offending `:token` or `nil`, the 0-based byte `:position`, and the 1-based
+  `:line` and `:column` of the problem.
+
+  ## Options
+
+    * `:lone_surrogates` - what to do with a `\u` escape naming a surrogate
+      that is not part of a pair: `:reject` (the default) returns an error,
+      `:replace` substitutes U+FFFD and `:wtf8` passes it through as WTF-8.
+
+    * `:invalid_utf8` - what to do with bytes inside a string that are not
+      valid UTF-8: `:reject` (the default) returns an `:invalid_utf8` error
+      pointing at the first bad byte, and `:replace` substitutes U+FFFD.
+
+    * `:max_depth` - how deeply objects and arrays may nest before the
+      document is rejected with `:max_depth_exceeded`. Defaults to `1024`.
+
+    * `:max_bytes`, `:max_string_length`, `:max_elements` and
+      `:max_number_digits` - limits for untrusted input on the size of the
+      whole document, the byte length of any unescaped string or key, the
+      number of members of any one object or array, and the digits in any
+      number. Exceeding them returns `:input_too_large`, `:string_too_long`,
+      `:too_many_elements` or `:number_too_long` respectively. Each defaults
+      to `:infinity`, which `:max_depth` also accepts.
+
+    * `:keys` - how object keys are decoded: `:strings` (the default),
+      `:atoms`, or `:existing_atoms`, which returns an error for keys that
+      don't name an existing atom so untrusted input can't exhaust the atom
+      table.
+
+    * `:duplicate_keys` - what to do when an object repeats a key: `:last`
+      (the default) keeps the last value, `:first` keeps the first, and
+      `:reject` returns a `:duplicate_key` error naming the key.
+
+    * `:objects` - how objects are decoded: `:maps` (the default), which
+      loses the order of the members, `:proplists`, which returns lists of
+      `{key, value}` tuples in the order of the document, or `:ejson`,
+      which wraps those lists in a 1-tuple as jiffy does. `:duplicate_keys`
+      applies to all of them, with a kept value staying where its key first
+      appeared. With `:proplists` an empty object decodes to `[]`, just as
+      an empty array does, so it encodes back as `[]`. Use `:ejson` when the
+      two must be told apart.
+
+    * `:copy_strings` - strings without escapes are returned as sub-binaries
+      of the input, which keeps the whole input alive as long as any of them
+      is. Set to `true` to copy them instead. Defaults to `false`.
+
+    * `:floats` - how numbers with a fraction or an exponent are decoded:
+      `:native` (the default) rounds them to the nearest float, and
+      `:decimals` returns `%Decimal{}` structs holding exactly the digits in
+      the document, without going through a float. Only the struct is built,
+      so the `decimal` package is only needed to work with the results.
+
+    * `:integral_floats` - numbers are integers or floats by how they are
+      written, so `1.0` and `1e2` decode to floats. Set to `true` to decode
+      those with an integral value to integers instead, which applies to
+      `floats: :decimals` too. Defaults to `false`.
+
+    * `:raw_numbers` - set to `true` to decode every number, integer or
+      not, to a `{:json_number, text}` tuple holding its text exactly as
+      written, so that `encode/2` can write it back out unchanged. Takes
+      precedence over `:floats`. Defaults to `false`.
+
+    * `:non_finite` - set to `true` to accept `NaN`, `Infinity` and
+      `-Infinity`, which JavaScript and Python's `json` module write for
+      non-finite floats, decoding them to `:nan`, `:infinity` and
+      `:negative_infinity`. Pass a map such as `%{nan: :undefined}` instead
+      to decode to other atoms. Otherwise they return a
+      `:non_finite_number` error. Floats too large for a double, such as
+      `1e400`, decode to the infinity atoms when this is set and return an
+      `:invalid_number` error otherwise.
+
+    * `:null_term` - the atom `null` decodes to, such as `:null` or
+      `:undefined` for Erlang code. Defaults to `nil`.
+
+    * `:lenient` - set to `true` to accept the JSON5 conveniences found in
+      hand-written files: `//` and `/* */` comments, trailing commas in
+      arrays and objects, single-quoted strings, unquoted keys made of ASCII
+      letters, digits, `_` and `$`, hexadecimal integers of any size, and
+      numbers, `Infinity` included, with a leading `+` or `.`. With
+      `decode_lines/2`, a line holding only comments is blank. Defaults to
+      `false`.
+
   ## Examples
     iex> Json.decode(~s|{"hello":"world"}|)
     {:ok, %{"hello" => "world"}}
@@ -15,18 +136,45 @@ defmodule Json do
     iex> Json.decode(~s|{"lists":[1,2,3]}|)
     {:ok, %{"lists" => [1,2,3]}}
 
+    iex> Json.decode(~s|{"hello":"world"}|, keys: :atoms)
+    {:ok, %{hello: "world"}}
+
+    iex> Json.decode("[19.990000000000001]", floats: :decimals)
+    {:ok, [%{__struct__: Decimal, sign: 1, coef: 19990000000000001, exp: -15}]}
+
+    iex> Json.decode(~s|{"b": 1, "a": 2}|, objects: :proplists)
+    {:ok, [{"b", 1}, {"a", 2}]}
+
+    iex> Json.decode("[1.0, 1e2, 1.5]", integral_floats: true)
+    {:ok, [1, 100, 1.5]}
+
+    iex> Json.decode("[1.000, 1E+2]", raw_numbers: true)
+    {:ok, [{:json_number, "1.000"}, {:json_number, "1E+2"}]}
+
+    iex> Json.decode("{a: [1, 2,]} // comment", lenient: true)
+    {:ok, %{"a" => [1, 2]}}
+
+    iex> Json.decode("[null]", null_term: :undefined)
+    {:ok, [:undefined]}
+
+    iex> Json.decode(~s|"\\uD800"|, lone_surrogates: :replace)
+    {:ok, "\uFFFD"}
+
+    iex> Json.decode(~s|{"a": 1,\n "b" 2}|)
+    {:error, %{reason: :unexpected_token, token: "2", position: 14, line: 2, column: 6}}
+
   """
-  def decode(data), do: threaded_decode(data)
+  def decode(data, opts \\ []), do: threaded_decode(data, opts)
 
-  def decode!(data) do
-    case decode(data) do
+  def decode!(data, opts \\ []) do
+    case decode(data, opts) do
       {:ok, result} -> result
-      {:error, error} -> raise Error, message: error
+      {:error, error} -> raise Error, error
     end
   end
 
-  def threaded_decode(data) do
-    :ok = Native.decode_threaded(data)
+  def threaded_decode(data, opts \\ []) do
+    :ok = Native.decode_threaded(data, Map.new(opts))
 
     receive do
       {:ok, result} ->
@@ -40,12 +188,127 @@ defmodule Json do
     end
   end
 
-  def parse(data) do
+  @doc ~S"""
+  Decodes a binary string into Elixir terms using the resumable parser,
+  yielding back to the scheduler between chunks of work.
+
+  Accepts the same options as `decode/2`.
+
+  ## Examples
+    iex> Json.parse(~s|["\\u2603", "\\uD834\\uDD1E"]|)
+    {:ok, ["☃", "𝄞"]}
+
+  """
+  def parse(data, opts \\ []) do
     data
-    |> Native.decode_init()
+    |> Native.decode_init(Map.new(opts))
     |> handle_parse_result()
   end
 
+  def parse!(data, opts \\ []) do
+    case parse(data, opts) do
+      {:ok, result} -> result
+      {:error, error} -> raise Error, error
+    end
+  end
+
+  @doc ~S"""
+  Decodes a document that arrives in chunks, such as from `File.stream!/3`
+  or a socket, without concatenating them first. Accepts the same options as
+  `decode/2`, except that strings are always copied.
+
+  ## Examples
+
+      iex> Json.decode_stream([~s({"hel), ~s(lo": [1), ~s(2, 3]})])
+      {:ok, %{"hello" => [12, 3]}}
+
+  """
+  def decode_stream(chunks, opts \\ []) do
+    stream = Native.decode_stream_init(Map.new(opts))
+
+    result =
+      Enum.reduce_while(chunks, {:more, stream}, fn chunk, {:more, stream} ->
+        case Native.decode_stream_feed(stream, chunk) do
+          {:more, stream} -> {:cont, {:more, stream}}
+          {:error, error} -> {:halt, {:error, error}}
+        end
+      end)
+
+    case result do
+      {:more, stream} -> Native.decode_stream_finish(stream)
+      {:error, error} -> {:error, error}
+    end
+  end
+
+  @doc ~S"""
+  Decodes newline-delimited JSON (JSON Lines), with one document per line,
+  into a list. Blank lines are skipped. Errors report the line the problem
+  is on. Accepts the same options as `decode/2`.
+
+  ## Examples
+
+      iex> Json.decode_lines(~s({"a": 1}\n\n[2]\n))
+      {:ok, [%{"a" => 1}, [2]]}
+
+      iex> {:error, %{line: 2}} = Json.decode_lines(~s({"a": 1}\n[2,\n))
+
+  """
+  def decode_lines(data, opts \\ []), do: Native.decode_lines_dirty(data, Map.new(opts))
+
+  @doc """
+  Like `decode_lines/2`, but lazily decodes one line at a time. Each element
+  is `{:ok, document}` or `{:error, details}`; a line that fails to decode
+  doesn't stop the ones after it.
+  """
+  def stream_lines(data, opts \\ []) do
+    Stream.resource(
+      fn -> Native.decode_lines_init(data, Map.new(opts)) end,
+      fn lines ->
+        case Native.decode_lines_next(lines) do
+          :done -> {:halt, lines}
+          result -> {[result], lines}
+        end
+      end,
+      fn _ -> :ok end
+    )
+  end
+
+  @doc ~S"""
+  Decodes a sequence of documents into a list. The documents may be
+  concatenated, with or without whitespace between them, or framed as an
+  RFC 7464 JSON text sequence, with a record separator (`0x1E`) before
+  each one. A number or literal must be followed by whitespace, a record
+  separator or the end, so `"01"` is an error rather than two documents.
+  Accepts the same options as `decode/2`.
+
+  ## Examples
+
+      iex> Json.decode_sequence(~s({"a":1}{"b":2} [3]))
+      {:ok, [%{"a" => 1}, %{"b" => 2}, [3]]}
+
+      iex> Json.decode_sequence(<<0x1E, "1\n", 0x1E, "[2]\n">>)
+      {:ok, [1, [2]]}
+
+  """
+  def decode_sequence(data, opts \\ []), do: Native.decode_sequence_dirty(data, Map.new(opts))
+
+  @doc ~S"""
+  Decodes the next document of a sequence, as accepted by
+  `decode_sequence/2`, starting at byte `offset`. Returns the document
+  along with the offset just past it, to resume from, or `:done` once only
+  whitespace and separators are left.
+
+  ## Examples
+
+      iex> {:ok, %{"a" => 1}, offset} = Json.decode_next(~s({"a":1} [2]), 0)
+      iex> Json.decode_next(~s({"a":1} [2]), offset)
+      {:ok, [2], 11}
+      iex> Json.decode_next(~s({"a":1} [2]), 11)
+      :done
+
+  """
+  def decode_next(data, offset, opts \\ []), do: Native.decode_next(data, offset, Map.new(opts))
+
   def handle_parse_result(result) do
     case result do
       {:ok, result} ->
@@ -64,17 +327,57 @@ defmodule Json do
   @doc ~S"""
   Decodes a map or struct into a JSON string.
 
+  ## Options
+
+    * `:non_finite` - set to `true` to encode the atoms `:nan`, `:infinity`
+      and `:negative_infinity` as `NaN`, `Infinity` and `-Infinity`, or to
+      a map such as `%{infinity: :inf}` to use other atoms, as with
+      `decode/2`. These are not valid JSON, but JavaScript and Python's
+      `json` module read them. Otherwise such atoms encode as strings, like
+      any other atom.
+
+    * `:null_term` - the atom, or list of atoms, written as `null` in place
+      of `nil`, which then encodes as a string like any other atom. Pass
+      `[nil, :undefined]` to keep `nil` too. Booleans are always written as
+      `true` and `false`.
+
+  Besides maps, objects may be given as proplists, non-empty lists of
+  `{key, value}` tuples, or as proplists wrapped in a 1-tuple, as decoded
+  with the `:objects` option. Their members are written in order, including
+  any repeated keys. An empty proplist is written as `[]`, so use
+  `{[]}` for an empty object whose order matters.
+
+  Tuples of the form `{:json_number, text}`, as decoded with the
+  `:raw_numbers` option, are written out as the number in `text` verbatim.
+  The text must be a valid JSON number, or `{:invalid_number, term}` is
+  returned.
+
+  Unlike decoding, encoding has no nesting limit: any term that fits in
+  memory can be encoded.
+
   ## Examples
     iex> Json.encode(%{hello: "world",list: [%{a: "b"}]})
     {:ok, ~s({"hello":"world","list":[{"a":"b"}]})}
 
+    iex> Json.encode([1.5, :infinity], non_finite: true)
+    {:ok, "[1.5,Infinity]"}
+
+    iex> Json.encode({[{"b", 1}, {"a", [{:c, 2}]}]})
+    {:ok, ~s({"b":1,"a":{"c":2}})}
+
+    iex> Json.encode([nil, :undefined], null_term: [nil, :undefined])
+    {:ok, "[null,null]"}
+
+    iex> Json.encode(%{"price" => {:json_number, "19.90"}})
+    {:ok, ~s({"price":19.90})}
+
   """
-  def encode!(data) do
-    case encode(data) do
+  def encode!(data, opts \\ []) do
+    case encode(data, opts) do
       {:ok, result} -> result
-      {:error, error} -> raise Error, message: error
+      {:error, {reason, term}} -> raise Error, message: "#{reason}: #{inspect(term)}"
     end
   end
 
-  def encode(data), do: Native.encode_dirty(data)
+  def encode(data, opts \\ []), do: Native.encode_dirty(data, Map.new(opts))
 end
@@ -4,12 +4,21 @@ defmodule Json.Native do
   """
   use Rustler, otp_app: :fast_json
 
-  def decode_naive(_), do: nif_error()
-  def decode_init(_), do: nif_error()
+  def decode_naive(_, _), do: nif_error()
+  def decode_init(_, _), do: nif_error()
   def decode_iter(_, _), do: nif_error()
-  def decode_dirty(_), do: nif_error()
-  def decode_threaded(_), do: nif_error()
-  def encode_dirty(_), do: nif_error()
+  def decode_dirty(_, _), do: nif_error()
+  def decode_threaded(_, _), do: nif_error()
+  def decode_lines_dirty(_, _), do: nif_error()
+  def decode_lines_init(_, _), do: nif_error()
+  def decode_lines_next(_), do: nif_error()
+  def decode_sequence_dirty(_, _), do: nif_error()
+  def decode_next(_, _, _), do: nif_error()
+  def decode_stream_init(_), do: nif_error()
+  def decode_stream_feed(_, _), do: nif_error()
+  def decode_stream_finish(_), do: nif_error()
+  def encode(_, _), do: nif_error()
+  def encode_dirty(_, _), do: nif_error()
 
   defp nif_error, do: :erlang.nif_error(:nif_not_loaded)
 end
@@ -1,8 +1,78 @@
 rustler::atoms! {
     more,
+    done,
     ok,
     error,
     nil,
     __true__ = "true",
     __false__ = "false",
+
+    // Decode options
+    lone_surrogates,
+    reject,
+    replace,
+    wtf8,
+    invalid_utf8,
+    keys,
+    strings,
+    atoms,
+    existing_atoms,
+    duplicate_keys,
+    objects,
+    maps,
+    proplists,
+    ejson,
+    copy_strings,
+    null_term,
+    lenient,
+    non_finite,
+    nan,
+    negative_infinity,
+    first,
+    last,
+    max_depth,
+    max_bytes,
+    max_string_length,
+    max_elements,
+    max_number_digits,
+    infinity,
+    floats,
+    native,
+    decimals,
+    integral_floats,
+    raw_numbers,
+    json_number,
+
+    // Decimal structs
+    __struct__ = "__struct__",
+    decimal = "Elixir.Decimal",
+    sign,
+    coef,
+    exp,
+
+    // Decode errors
+    reason,
+    token,
+    position,
+    line,
+    column,
+    unexpected_end,
+    unexpected_token,
+    invalid_number,
+    invalid_escape,
+    lone_surrogate,
+    control_character,
+    max_depth_exceeded,
+    input_too_large,
+    string_too_long,
+    too_many_elements,
+    number_too_long,
+    non_finite_number,
+    unknown_atom,
+    invalid_atom,
+    duplicate_key,
+
+    // Encode errors
+    unsupported_type,
+    invalid_key,
 }
@@ -1,40 +1,81 @@
 use std::sync::Mutex;
 
-use json::{self, JsonValue};
+use rustler::env::SavedTerm;
 use rustler::schedule::consume_timeslice;
-use rustler::types::map::map_new;
-use rustler::{Atom, Encoder, Env, Error, OwnedEnv, ResourceArc, Term};
+use rustler::{Atom, Binary, Encoder, Env, Error, OwnedEnv, ResourceArc, Term};
 
 use crate::atoms;
-use crate::parser::Parser;
+use crate::errors;
+use crate::options::DecodeOptions;
+use crate::parser::{Parser, ParserState};
 use crate::sink::TermSink;
-use crate::util::{error, ok};
+use crate::util::{error, error_term, ok};
 use crate::POOL;
 
-pub struct ParserResource(Mutex<Parser>);
+pub struct ParserResource {
+    decoding: Mutex<Decoding>,
+    options: DecodeOptions,
+}
+
+/// The source binary and how far the parser got into it. Saving the binary in
+/// an `OwnedEnv` keeps it alive between `decode_iter` calls without copying
+/// it, since only the reference count of a large binary changes.
+struct Decoding {
+    env: OwnedEnv,
+    source: SavedTerm,
+    state: ParserState,
+}
 
 impl ParserResource {
-    fn new(data: String) -> ParserResource {
-        ParserResource(Mutex::new(Parser::new(data)))
+    fn new(env: Env, data: Binary, options: DecodeOptions) -> ParserResource {
+        let owned_env = OwnedEnv::new();
+        let source = owned_env.save(data.to_term(env));
+
+        ParserResource {
+            decoding: Mutex::new(Decoding {
+                env: owned_env,
+                source,
+                state: ParserState::default(),
+            }),
+            options,
+        }
     }
 }
 
 #[rustler::nif]
-pub fn decode_naive(env: Env, data: String) -> Result<Term, Error> {
-    parse_json(env, data)
+pub fn decode_naive<'a>(
+    env: Env<'a>,
+    data: Binary<'a>,
+    options: DecodeOptions,
+) -> Result<Term<'a>, Error> {
+    match parse_json(env, data, options) {
+        Ok(term) => ok(env, term),
+        Err(err) => error(env, err),
+    }
 }
 
 #[rustler::nif(schedule = "DirtyCpu")]
-pub fn decode_dirty(env: Env, data: String) -> Result<Term, Error> {
-    parse_json(env, data)
+pub fn decode_dirty<'a>(
+    env: Env<'a>,
+    data: Binary<'a>,
+    options: DecodeOptions,
+) -> Result<Term<'a>, Error> {
+    match parse_json(env, data, options) {
+        Ok(term) => ok(env, term),
+        Err(err) => error(env, err),
+    }
 }
 
 #[rustler::nif]
-pub fn decode_init<'a>(data: String) -> (Atom, ResourceArc<ParserResource>, Vec<Term<'a>>) {
-    let resource = ResourceArc::new(ParserResource::new(data));
+pub fn decode_init<'a>(
+    env: Env<'a>,
+    data: Binary<'a>,
+    options: DecodeOptions,
+) -> Result<(Atom, ResourceArc<ParserResource>, Vec<Term<'a>>), Error> {
+    let resource = ResourceArc::new(ParserResource::new(env, data, options));
     let vector: Vec<Term> = vec![];
 
-    (atoms::more(), resource, vector)
+    Ok((atoms::more(), resource, vector))
 }
 
 #[rustler::nif]
@@ -43,11 +84,18 @@ pub fn decode_iter<'a>(
     resource: ResourceArc<ParserResource>,
     stack: Vec<Term<'a>>,
 ) -> Result<Term<'a>, Error> {
-    let mut sink = TermSink::new(env, stack);
-    let mut parser = match resource.0.try_lock() {
+    let mut decoding = match resource.decoding.try_lock() {
         Err(_) => return Err(Error::BadArg),
         Ok(guard) => guard,
     };
+    let source = decoding
+        .env
+        .run(|owned_env| decoding.source.load(owned_env).in_env(env));
+    let data = Binary::from_term(source)?;
+
+    let state = std::mem::take(&mut decoding.state);
+    let mut parser = Parser::resume(data.as_slice(), state, resource.options.parser);
+    let mut sink = TermSink::new(env, stack, Some(data), resource.options.sink);
 
     while !consume_timeslice(env, 1) {
         match parser.parse(&mut sink) {
@@ -57,72 +105,40 @@ pub fn decode_iter<'a>(
         }
     }
 
-    Ok((atoms::more(), resource.clone(), sink.to_stack()).encode(env))
+    decoding.state = parser.suspend();
+    Ok((atoms::more(), resource.clone(), sink.into_stack()).encode(env))
 }
 
 #[rustler::nif]
-pub fn decode_threaded(env: Env, data: Term) -> Atom {
+pub fn decode_threaded(env: Env, data: Binary, options: DecodeOptions) -> Result<Atom, Error> {
     let mut owned_env = OwnedEnv::new();
 
-    let source = owned_env.save(data);
+    let source = owned_env.save(data.to_term(env));
     let caller_pid = env.pid();
 
     POOL.spawn(move || {
         owned_env.send_and_clear(&caller_pid, |env| {
-            match source.load(env).decode::<String>() {
-                Ok(source) => match json::parse(&source) {
-                    Ok(json) => {
-                        let term = json_to_term(env, json);
-                        (atoms::ok(), term).encode(env)
-                    }
-                    Err(err) => {
-                        let error = format!("{}", err).encode(env);
-                        (atoms::error(), error).encode(env)
-                    }
-                },
-                Err(_) => atoms::error().encode(env),
+            // Saved from a `Binary` above, so this always decodes.
+            let data = Binary::from_term(source.load(env)).unwrap();
+            match parse_json(env, data, options) {
+                Ok(term) => (atoms::ok(), term).encode(env),
+                Err(err) => error_term(env, err),
             }
         });
     });
 
-    atoms::ok()
-}
-
-fn parse_json(env: Env, data: String) -> Result<Term, Error> {
-    match json::parse(&data) {
-        Ok(json) => {
-            let term = json_to_term(env, json);
-            Ok((atoms::ok(), term).encode(env))
-        }
-        Err(err) => {
-            let error = format!("{}", err).encode(env);
-            Ok((atoms::error(), error).encode(env))
-        }
-    }
+    Ok(atoms::ok())
 }
 
-fn json_to_term<'a>(env: Env<'a>, value: JsonValue) -> Term<'a> {
-    match value {
-        JsonValue::Null => atoms::nil().encode(env),
-        JsonValue::Short(s) => s.encode(env),
-        JsonValue::String(s) => s.encode(env),
-        JsonValue::Number(n) => {
-            let (_, _, exponent) = n.as_parts();
-            if exponent != 0 {
-                f64::from(n).encode(env)
-            } else {
-                i64::from(n).encode(env)
-            }
-        }
-        JsonValue::Boolean(b) => b.encode(env),
-        JsonValue::Object(mut obj) => obj.iter_mut().fold(map_new(env), |map, (key, value)| {
-            let key_term = key.encode(env);
-            let value_term = json_to_term(env, value.take());
-            map.map_put(key_term, value_term).ok().unwrap()
-        }),
-        JsonValue::Array(values) => {
-            let terms: Vec<Term<'a>> = values.into_iter().map(|v| json_to_term(env, v)).collect();
-            terms.encode(env)
-        }
-    }
+/// Runs the `Parser` to completion without yielding, for the entry points
+/// that don't need to share the scheduler.
+fn parse_json<'a>(
+    env: Env<'a>,
+    data: Binary<'a>,
+    options: DecodeOptions,
+) -> errors::Result<Term<'a>> {
+    let mut parser = Parser::new(data.as_slice(), options.parser);
+    let mut sink = TermSink::new(env, vec![], Some(data), options.sink);
+    while !parser.parse(&mut sink)? {}
+    Ok(sink.pop())
 }
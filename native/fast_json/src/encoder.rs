@@ -1,75 +1,292 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::fmt::Write;
+
 use crate::atoms;
-use crate::util::ok;
+use crate::options::EncodeOptions;
+use crate::parser;
+use crate::util::{bignum_digits, ok};
 use json::JsonValue;
-use rustler::{Atom, Decoder, Encoder, Env, Error, ListIterator, MapIterator, Term};
+use rustler::dynamic::TermType;
+use rustler::types::tuple::get_tuple;
+use rustler::{Atom, Binary, Decoder, Encoder, Env, Error, ListIterator, MapIterator, Term};
 
-pub fn encode<'a>(env: Env<'a>, args: &[Term<'a>]) -> Result<Term<'a>, Error> {
-    let json_val = term_to_json(env, args[0].decode()?)?;
-    let json_str = json::stringify(json_val);
+#[rustler::nif]
+pub fn encode<'a>(env: Env<'a>, term: Term<'a>, options: EncodeOptions) -> Result<Term<'a>, Error> {
+    encode_term(env, term, options)
+}
 
-    ok(env, json_str.encode(env))
+#[rustler::nif(schedule = "DirtyCpu")]
+pub fn encode_dirty<'a>(
+    env: Env<'a>,
+    term: Term<'a>,
+    options: EncodeOptions,
+) -> Result<Term<'a>, Error> {
+    encode_term(env, term, options)
 }
 
-fn term_to_json<'a>(env: Env<'a>, term: Term<'a>) -> Result<JsonValue, Error> {
-    if let Ok(string) = <&str as Decoder>::decode(term) {
-        handle_binary(env, string)
-    } else if let Ok(iter) = <ListIterator as Decoder>::decode(term) {
-        handle_list(env, iter)
-    } else if let Ok(atom) = Atom::from_term(term) {
-        handle_atom(env, atom)
-    } else if let Ok(number) = <f64 as Decoder>::decode(term) {
-        handle_float(env, number)
-    } else if let Ok(number) = <i64 as Decoder>::decode(term) {
-        handle_integer(env, number)
-    } else if let Ok(iter) = <MapIterator as Decoder>::decode(term) {
-        handle_map(env, iter)
-    } else {
-        panic!("fail")
+fn encode_term<'a>(
+    env: Env<'a>,
+    term: Term<'a>,
+    options: EncodeOptions,
+) -> Result<Term<'a>, Error> {
+    let mut writer = Writer {
+        env,
+        options,
+        out: String::new(),
+    };
+    match writer.write_term(term) {
+        Ok(()) => ok(env, writer.out.encode(env)),
+        Err(reason) => Ok((atoms::error(), reason).encode(env)),
     }
 }
 
-fn handle_map<'a>(env: Env<'a>, iter: MapIterator<'a>) -> Result<JsonValue, Error> {
-    use rustler::dynamic::TermType;
+/// Writes terms out as JSON text. On failure the error holds the reason term,
+/// e.g. `{:unsupported_type, term}`, which is returned to the caller.
+///
+/// This used to build a `JsonValue` for `json::stringify`, but a `JsonValue`
+/// cannot hold a bare `NaN` or `Infinity`, nor a number that is only text,
+/// such as a bignum or a raw number. The output is otherwise the same:
+/// strings are escaped as `json` escapes them, floats are formatted by `json`,
+/// and maps keep the first position of a key that is both an atom and a
+/// binary, as `json::object::Object::insert` does.
+struct Writer<'a> {
+    env: Env<'a>,
+    options: EncodeOptions,
+    out: String,
+}
 
-    let mut map = json::object::Object::new();
+/// The items left to write of an object or array.
+enum Items<'a> {
+    List(ListIterator<'a>),
+    /// The members of a proplist, written in order, keeping any duplicates.
+    Members(ListIterator<'a>),
+    Map(std::vec::IntoIter<(String, Term<'a>)>),
+}
 
-    for (key, value) in iter {
-        let key_string = match key.get_type() {
-            TermType::Atom => key.atom_to_string().ok().unwrap(),
-            TermType::Binary => key.decode().ok().unwrap(),
-            _ => return Err(Error::BadArg),
-        };
-        map.insert(&key_string, term_to_json(env, value)?);
+impl Items<'_> {
+    fn brackets(&self) -> (char, char) {
+        match self {
+            Items::List(_) => ('[', ']'),
+            Items::Members(_) | Items::Map(_) => ('{', '}'),
+        }
     }
-    Ok(JsonValue::Object(map))
 }
 
-fn handle_list<'a>(env: Env<'a>, iter: ListIterator<'a>) -> Result<JsonValue, Error> {
-    let values: Result<Vec<JsonValue>, _> = iter.map(|term| term_to_json(env, term)).collect();
-
-    Ok(JsonValue::Array(values?))
+struct Open<'a> {
+    items: Items<'a>,
+    first: bool,
 }
 
-fn handle_binary<'a>(_env: Env<'a>, string: &str) -> Result<JsonValue, Error> {
-    Ok(JsonValue::String(string.to_string()))
+impl<'a> Writer<'a> {
+    /// Objects and arrays are kept on a heap-allocated stack rather than
+    /// recursed into, so that deep nesting cannot overflow the native stack.
+    fn write_term(&mut self, term: Term<'a>) -> Result<(), Term<'a>> {
+        let mut open: Vec<Open<'a>> = vec![];
+        let mut next = Some(term);
+        loop {
+            if let Some(term) = next.take() {
+                if let Some(items) = self.write_value(term)? {
+                    self.out.push(items.brackets().0);
+                    open.push(Open { items, first: true });
+                }
+            }
+            let innermost = match open.last_mut() {
+                Some(innermost) => innermost,
+                None => return Ok(()),
+            };
+            next = self.next_item(innermost)?;
+            if next.is_none() {
+                self.out.push(innermost.items.brackets().1);
+                open.pop();
+            }
+        }
+    }
+
+    /// Writes a scalar, or returns the items of an object or array for the
+    /// caller to write.
+    fn write_value(&mut self, term: Term<'a>) -> Result<Option<Items<'a>>, Term<'a>> {
+        if let Ok(string) = <&str as Decoder>::decode(term) {
+            self.write_string(string);
+        } else if let Ok(iter) = <ListIterator as Decoder>::decode(term) {
+            return match term.list_get_cell() {
+                Ok((head, _)) if is_member(head) => Ok(Some(Items::Members(iter))),
+                _ => Ok(Some(Items::List(iter))),
+            };
+        } else if let Ok(atom) = Atom::from_term(term) {
+            self.write_atom(atom);
+        } else if let Ok(number) = <f64 as Decoder>::decode(term) {
+            self.write_float(number);
+        } else if let Ok(number) = <i64 as Decoder>::decode(term) {
+            write!(self.out, "{}", number).unwrap();
+        } else if let Ok(number) = <u64 as Decoder>::decode(term) {
+            write!(self.out, "{}", number).unwrap();
+        } else if term.is_number() {
+            // Floats and smaller integers were handled above.
+            self.out.push_str(&bignum_digits(term).unwrap());
+        } else if let Ok(iter) = <MapIterator as Decoder>::decode(term) {
+            return Ok(Some(self.map_items(iter)?));
+        } else if let TermType::Tuple = term.get_type() {
+            return self.write_tuple(term);
+        } else {
+            return Err((atoms::unsupported_type(), term).encode(self.env));
+        }
+        Ok(None)
+    }
+
+    /// Writes the separator and any key before the next item of an open
+    /// object or array, returning the item's value, or `None` once all have
+    /// been written.
+    fn next_item(&mut self, open: &mut Open<'a>) -> Result<Option<Term<'a>>, Term<'a>> {
+        let (key, value) = match &mut open.items {
+            Items::List(iter) => match iter.next() {
+                Some(value) => (None, value),
+                None => return Ok(None),
+            },
+            Items::Members(iter) => match iter.next() {
+                Some(member) => match get_tuple(member).as_deref() {
+                    Ok(&[key, value]) => (Some(self.key_string(key)?), value),
+                    _ => return Err((atoms::unsupported_type(), member).encode(self.env)),
+                },
+                None => return Ok(None),
+            },
+            Items::Map(iter) => match iter.next() {
+                Some((key, value)) => (Some(key), value),
+                None => return Ok(None),
+            },
+        };
+        if !open.first {
+            self.out.push(',');
+        }
+        open.first = false;
+        if let Some(key) = key {
+            self.write_string(&key);
+            self.out.push(':');
+        }
+        Ok(Some(value))
+    }
+
+    /// An atom key and a binary key may name the same member, in which case
+    /// the last value is written where the first key was.
+    fn map_items(&self, iter: MapIterator<'a>) -> Result<Items<'a>, Term<'a>> {
+        let mut members: Vec<(String, Term<'a>)> = vec![];
+        let mut index: HashMap<String, usize> = HashMap::new();
+
+        for (key, value) in iter {
+            match index.entry(self.key_string(key)?) {
+                Entry::Occupied(entry) => members[*entry.get()].1 = value,
+                Entry::Vacant(entry) => {
+                    members.push((entry.key().clone(), value));
+                    entry.insert(members.len() - 1);
+                }
+            }
+        }
+        Ok(Items::Map(members.into_iter()))
+    }
+
+    fn key_string(&self, key: Term<'a>) -> Result<String, Term<'a>> {
+        match (key.get_type(), key.decode::<String>()) {
+            (TermType::Atom, _) => Ok(key.atom_to_string().ok().unwrap()),
+            (TermType::Binary, Ok(string)) => Ok(string),
+            _ => Err((atoms::invalid_key(), key).encode(self.env)),
+        }
+    }
+
+    /// Writes a `{[...]}` object as jiffy does, or a `{:json_number, text}`
+    /// tuple, as decoded with `raw_numbers`, verbatim. The text must be a
+    /// number in the strict grammar, so that the output stays valid JSON.
+    fn write_tuple(&mut self, term: Term<'a>) -> Result<Option<Items<'a>>, Term<'a>> {
+        if let Ok((members,)) = term.decode::<(ListIterator,)>() {
+            return Ok(Some(Items::Members(members)));
+        }
+        match term.decode::<(Atom, Binary)>() {
+            Ok((tag, text)) if tag == atoms::json_number() => {
+                if !parser::is_number(&text) {
+                    return Err((atoms::invalid_number(), term).encode(self.env));
+                }
+                // Numbers are ASCII.
+                self.out.push_str(std::str::from_utf8(&text).unwrap());
+                Ok(None)
+            }
+            _ => Err((atoms::unsupported_type(), term).encode(self.env)),
+        }
+    }
+
+    fn write_string(&mut self, string: &str) {
+        write_string(&mut self.out, string);
+    }
+
+    fn write_atom(&mut self, atom: Atom) {
+        if atom == atoms::__true__() {
+            self.out.push_str("true");
+        } else if atom == atoms::__false__() {
+            self.out.push_str("false");
+        } else if self.options.is_null(atom) {
+            self.out.push_str("null");
+        } else if let Some(value) = self.options.non_finite.and_then(|atoms| atoms.number(atom)) {
+            self.out.push_str(value.name());
+        } else {
+            let name = atom.to_term(self.env).atom_to_string().ok().unwrap();
+            self.write_string(&name);
+        }
+    }
+
+    /// Floats are formatted by the `json` crate.
+    fn write_float(&mut self, number: f64) {
+        self.out.push_str(&JsonValue::Number(number.into()).dump());
+    }
 }
 
-fn handle_atom<'a>(_env: Env<'a>, atom: Atom) -> Result<JsonValue, Error> {
-    if atom == atoms::__true__() {
-        Ok(JsonValue::Boolean(true))
-    } else if atom == atoms::__false__() {
-        Ok(JsonValue::Boolean(false))
-    } else if atom == atoms::nil() {
-        Ok(JsonValue::Null)
-    } else {
-        Ok(JsonValue::String("nope".to_string()))
+/// Whether the first element of a list makes it a proplist, being a pair
+/// other than a raw number.
+fn is_member(head: Term) -> bool {
+    match get_tuple(head).as_deref() {
+        Ok(&[tag, text]) => !(atoms::json_number() == tag && text.is_binary()),
+        _ => false,
     }
 }
 
-fn handle_float<'a>(_env: Env<'a>, num: f64) -> Result<JsonValue, Error> {
-    Ok(JsonValue::Number(num.into()))
+fn write_string(out: &mut String, string: &str) {
+    out.push('"');
+    let mut start = 0;
+    for (i, byte) in string.bytes().enumerate() {
+        let escape = match byte {
+            b'"' => "\\\"",
+            b'\\' => "\\\\",
+            b'\n' => "\\n",
+            b'\r' => "\\r",
+            b'\t' => "\\t",
+            0x08 => "\\b",
+            0x0C => "\\f",
+            0x00..=0x1F => "",
+            _ => continue,
+        };
+        out.push_str(&string[start..i]);
+        if escape.is_empty() {
+            write!(out, "\\u{:04x}", byte).unwrap();
+        } else {
+            out.push_str(escape);
+        }
+        start = i + 1;
+    }
+    out.push_str(&string[start..]);
+    out.push('"');
 }
 
-fn handle_integer<'a>(_env: Env<'a>, num: i64) -> Result<JsonValue, Error> {
-    Ok(JsonValue::Number(num.into()))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings_like_the_json_crate() {
+        let mut strings: Vec<String> = (0..0x80u8)
+            .map(|byte| char::from(byte).to_string())
+            .collect();
+        strings.push("a\"b\\c\u{1}d\u{7f}é☃😀\u{2028}".to_string());
+        strings.push(String::new());
+        for string in strings {
+            let mut out = String::new();
+            write_string(&mut out, &string);
+            assert_eq!(out, json::stringify(string.as_str()), "{:?}", string);
+        }
+    }
 }
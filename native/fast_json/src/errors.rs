@@ -1,8 +1,105 @@
+use std::fmt;
+
+/// Why a document was rejected. Returned to Elixir as the `:reason` atom of
+/// the error map.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reason {
+    UnexpectedEnd,
+    UnexpectedToken,
+    InvalidNumber,
+    InvalidEscape,
+    InvalidUtf8,
+    LoneSurrogate,
+    ControlCharacter,
+    MaxDepthExceeded,
+    InputTooLarge,
+    StringTooLong,
+    TooManyElements,
+    NumberTooLong,
+    NonFiniteNumber,
+    UnknownAtom,
+    InvalidAtom,
+    DuplicateKey,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match *self {
+            Reason::UnexpectedEnd => "unexpected end of input",
+            Reason::UnexpectedToken => "unexpected token",
+            Reason::InvalidNumber => "invalid number",
+            Reason::InvalidUtf8 => "invalid UTF-8",
+            Reason::InvalidEscape => "invalid escape",
+            Reason::LoneSurrogate => "lone surrogate",
+            Reason::ControlCharacter => "unescaped control character",
+            Reason::MaxDepthExceeded => "maximum nesting depth exceeded",
+            Reason::InputTooLarge => "input too large",
+            Reason::StringTooLong => "string too long",
+            Reason::TooManyElements => "too many elements",
+            Reason::NumberTooLong => "number too long",
+            Reason::NonFiniteNumber => "non-finite number",
+            Reason::UnknownAtom => "key is not an existing atom",
+            Reason::InvalidAtom => "key cannot be an atom",
+            Reason::DuplicateKey => "duplicate key",
+        };
+        f.write_str(text)
+    }
+}
+
+/// Where in the source an error was found. `offset` is a 0-based byte
+/// offset, `line` and `column` are 1-based and count characters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Default for Position {
+    fn default() -> Position {
+        Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Position {
+    /// The position just past `bytes`, which start at this position.
+    pub fn advance(&self, bytes: &[u8]) -> Position {
+        let (line, line_start, column) = match bytes.iter().rposition(|&b| b == b'\n') {
+            Some(i) => {
+                let newlines = bytes[..i].iter().filter(|&&b| b == b'\n').count() + 1;
+                (self.line + newlines, i + 1, 1)
+            }
+            None => (self.line, 0, self.column),
+        };
+        // Count characters by skipping UTF-8 continuation bytes.
+        let column = column
+            + bytes[line_start..]
+                .iter()
+                .filter(|&&b| b & 0xC0 != 0x80)
+                .count();
+
+        Position {
+            offset: self.offset + bytes.len(),
+            line,
+            column,
+        }
+    }
+}
+
 error_chain! {
     errors {
-        InvalidJson(message: String, offset: usize) {
-            description(message)
-            display("{} at position {}", message, offset)
+        InvalidJson(reason: Reason, token: Option<String>, position: Position) {
+            description("invalid JSON")
+            display(
+                "{}{} at position {}",
+                reason,
+                token.as_ref().map_or(String::new(), |token| format!(" {:?}", token)),
+                position.offset
+            )
         }
     }
 }
@@ -2,15 +2,22 @@
 extern crate error_chain;
 
 use crate::decoder::ParserResource;
+use crate::lines::LinesResource;
+use crate::stream::StreamResource;
 use rustler::{Env, Term};
 use scoped_pool::Pool;
 
 mod atoms;
 mod decoder;
-//mod encoder;
+mod encoder;
 mod errors;
+mod lines;
+mod options;
 mod parser;
+mod scan;
+mod sequence;
 mod sink;
+mod stream;
 mod util;
 
 lazy_static::lazy_static! {
@@ -25,12 +32,24 @@ rustler::init! {
         decoder::decode_iter,
         decoder::decode_dirty,
         decoder::decode_threaded,
-        //encoder::encode,
+        lines::decode_lines_dirty,
+        lines::decode_lines_init,
+        lines::decode_lines_next,
+        sequence::decode_sequence_dirty,
+        sequence::decode_next,
+        stream::decode_stream_init,
+        stream::decode_stream_feed,
+        stream::decode_stream_finish,
+        encoder::encode,
+        encoder::encode_dirty,
     ],
     load = load
 }
 
+#[allow(non_local_definitions)]
 fn load(env: Env, _info: Term) -> bool {
     rustler::resource!(ParserResource, env);
+    rustler::resource!(StreamResource, env);
+    rustler::resource!(LinesResource, env);
     true
 }
@@ -0,0 +1,143 @@
+use std::sync::Mutex;
+
+use rustler::env::SavedTerm;
+use rustler::{Binary, Encoder, Env, Error, OwnedEnv, ResourceArc, Term};
+
+use crate::atoms;
+use crate::errors::{self, ErrorKind, Position, Reason};
+use crate::options::DecodeOptions;
+use crate::parser::{Parser, ParserState};
+use crate::sink::TermSink;
+use crate::util::{error, ok};
+
+/// Newline-delimited JSON, also known as JSON Lines: one document per line.
+/// Lines holding only whitespace, or comments when lenient, are skipped, and
+/// a `\r` before the `\n` is whitespace like any other.
+pub struct LinesResource {
+    lines: Mutex<Lines>,
+    options: DecodeOptions,
+}
+
+struct Lines {
+    env: OwnedEnv,
+    source: SavedTerm,
+    /// Where the next line starts.
+    next: Position,
+    /// Set when the whole source is over `max_bytes`, to be returned in
+    /// place of the first line.
+    too_large: Option<errors::Error>,
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+pub fn decode_lines_dirty<'a>(
+    env: Env<'a>,
+    data: Binary<'a>,
+    options: DecodeOptions,
+) -> Result<Term<'a>, Error> {
+    if let Err(err) = check_size(&data, options) {
+        return error(env, err);
+    }
+    let mut documents = vec![];
+    let mut next = Position::default();
+
+    while next.offset < data.len() {
+        match parse_line(env, &data, &mut next, options) {
+            Ok(Some(document)) => documents.push(document),
+            Ok(None) => {}
+            Err(err) => return error(env, err),
+        }
+    }
+    ok(env, documents.encode(env))
+}
+
+#[rustler::nif]
+pub fn decode_lines_init(
+    env: Env,
+    data: Binary,
+    options: DecodeOptions,
+) -> ResourceArc<LinesResource> {
+    let owned_env = OwnedEnv::new();
+    let source = owned_env.save(data.to_term(env));
+
+    ResourceArc::new(LinesResource {
+        lines: Mutex::new(Lines {
+            env: owned_env,
+            source,
+            next: Position::default(),
+            too_large: check_size(&data, options).err(),
+        }),
+        options,
+    })
+}
+
+/// Returns the next document, or `:done`. A line that fails to parse is
+/// skipped after its error is returned, so the caller can carry on.
+#[rustler::nif]
+pub fn decode_lines_next<'a>(
+    env: Env<'a>,
+    resource: ResourceArc<LinesResource>,
+) -> Result<Term<'a>, Error> {
+    let mut lines = match resource.lines.try_lock() {
+        Err(_) => return Err(Error::BadArg),
+        Ok(guard) => guard,
+    };
+    let source = lines
+        .env
+        .run(|owned_env| lines.source.load(owned_env).in_env(env));
+    let data = Binary::from_term(source)?;
+
+    if let Some(err) = lines.too_large.take() {
+        lines.next.offset = data.len();
+        return error(env, err);
+    }
+    while lines.next.offset < data.len() {
+        match parse_line(env, &data, &mut lines.next, resource.options) {
+            Ok(Some(document)) => return ok(env, document),
+            Ok(None) => {}
+            Err(err) => return error(env, err),
+        }
+    }
+    Ok(atoms::done().encode(env))
+}
+
+/// Fails if `data` is over `max_bytes`, pointing at where the limit is passed.
+fn check_size(data: &[u8], options: DecodeOptions) -> errors::Result<()> {
+    let max_bytes = options.parser.max_bytes;
+    if data.len() <= max_bytes {
+        return Ok(());
+    }
+    let position = Position::default().advance(&data[..max_bytes]);
+    Err(ErrorKind::InvalidJson(Reason::InputTooLarge, None, position).into())
+}
+
+/// Parses the line starting at `next`, if it isn't blank, and moves `next`
+/// on to the following line.
+fn parse_line<'a>(
+    env: Env<'a>,
+    data: &Binary<'a>,
+    next: &mut Position,
+    options: DecodeOptions,
+) -> errors::Result<Option<Term<'a>>> {
+    let start = next.offset;
+    let end = match data[start..].iter().position(|&b| b == b'\n') {
+        Some(length) => start + length,
+        None => data.len(),
+    };
+    let origin = *next;
+    *next = Position {
+        offset: (end + 1).min(data.len()),
+        line: origin.line + 1,
+        column: 1,
+    };
+
+    let line = &data[start..end];
+    let mut parser = Parser::resume(line, ParserState::starting_at(origin), options.parser);
+    if parser.skip_blank() {
+        return Ok(None);
+    }
+
+    let source = data.make_subbinary(start, line.len()).unwrap();
+    let mut sink = TermSink::new(env, vec![], Some(source), options.sink);
+    while !parser.parse(&mut sink)? {}
+    Ok(Some(sink.pop()))
+}
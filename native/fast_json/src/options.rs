@@ -0,0 +1,227 @@
+use rustler::{Atom, Decoder, Error, MapIterator, NifResult, Term};
+
+use crate::atoms;
+use crate::parser::{InvalidUtf8, LoneSurrogates, ParserOptions};
+use crate::sink::{Duplicates, Floats, Keys, NonFiniteAtoms, NullTerm, Objects, SinkOptions};
+
+/// Options accepted by every decode NIF, decoded once from the map passed in
+/// from Elixir so that each scheduling strategy behaves the same way.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DecodeOptions {
+    pub parser: ParserOptions,
+    pub sink: SinkOptions,
+}
+
+impl<'a> Decoder<'a> for DecodeOptions {
+    fn decode(term: Term<'a>) -> NifResult<DecodeOptions> {
+        let mut options = DecodeOptions::default();
+
+        for (key, value) in term.decode::<MapIterator>()? {
+            let key: Atom = key.decode()?;
+            if key == atoms::lone_surrogates() {
+                options.parser.lone_surrogates = decode_lone_surrogates(value)?;
+            } else if key == atoms::invalid_utf8() {
+                options.parser.invalid_utf8 = decode_invalid_utf8(value)?;
+            } else if key == atoms::max_depth() {
+                options.parser.max_depth = decode_limit(value)?;
+            } else if key == atoms::max_bytes() {
+                options.parser.max_bytes = decode_limit(value)?;
+            } else if key == atoms::max_string_length() {
+                options.parser.max_string_length = decode_limit(value)?;
+            } else if key == atoms::max_elements() {
+                options.parser.max_elements = decode_limit(value)?;
+            } else if key == atoms::max_number_digits() {
+                options.parser.max_number_digits = decode_limit(value)?;
+            } else if key == atoms::lenient() {
+                options.parser.lenient = value.decode()?;
+            } else if key == atoms::non_finite() {
+                let non_finite = decode_non_finite(value)?;
+                options.parser.non_finite = non_finite.is_some();
+                options.sink.non_finite = non_finite;
+            } else if key == atoms::keys() {
+                options.sink.keys = decode_keys(value)?;
+            } else if key == atoms::duplicate_keys() {
+                options.sink.duplicates = decode_duplicates(value)?;
+            } else if key == atoms::objects() {
+                options.sink.objects = decode_objects(value)?;
+            } else if key == atoms::floats() {
+                options.sink.floats = decode_floats(value)?;
+            } else if key == atoms::integral_floats() {
+                options.sink.integral_floats = value.decode()?;
+            } else if key == atoms::raw_numbers() {
+                options.parser.raw_numbers = value.decode()?;
+            } else if key == atoms::null_term() {
+                options.sink.null_term = NullTerm(value.decode()?);
+            } else if key == atoms::copy_strings() {
+                options.sink.copy_strings = value.decode()?;
+            } else {
+                return Err(Error::BadArg);
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Options accepted by the encode NIFs.
+#[derive(Clone, Debug, Default)]
+pub struct EncodeOptions {
+    pub non_finite: Option<NonFiniteAtoms>,
+    /// The atoms written as `null`, just `nil` unless set.
+    pub null_terms: Option<Vec<NullTerm>>,
+}
+
+impl EncodeOptions {
+    pub fn is_null(&self, atom: Atom) -> bool {
+        match &self.null_terms {
+            Some(null_terms) => null_terms.contains(&NullTerm(atom)),
+            None => atom == atoms::nil(),
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for EncodeOptions {
+    fn decode(term: Term<'a>) -> NifResult<EncodeOptions> {
+        let mut options = EncodeOptions::default();
+
+        for (key, value) in term.decode::<MapIterator>()? {
+            let key: Atom = key.decode()?;
+            if key == atoms::non_finite() {
+                options.non_finite = decode_non_finite(value)?;
+            } else if key == atoms::null_term() {
+                options.null_terms = Some(decode_null_terms(value)?);
+            } else {
+                return Err(Error::BadArg);
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// `true` for the default atoms, `false` for none, or a map overriding any
+/// of the defaults.
+fn decode_non_finite(term: Term) -> NifResult<Option<NonFiniteAtoms>> {
+    if let Ok(enabled) = term.decode::<bool>() {
+        return Ok(if enabled {
+            Some(NonFiniteAtoms::default())
+        } else {
+            None
+        });
+    }
+
+    let mut non_finite = NonFiniteAtoms::default();
+    for (key, value) in term.decode::<MapIterator>()? {
+        let (key, value): (Atom, Atom) = (key.decode()?, value.decode()?);
+        if key == atoms::nan() {
+            non_finite.nan = value;
+        } else if key == atoms::infinity() {
+            non_finite.infinity = value;
+        } else if key == atoms::negative_infinity() {
+            non_finite.negative_infinity = value;
+        } else {
+            return Err(Error::BadArg);
+        }
+    }
+    Ok(Some(non_finite))
+}
+
+/// An atom, or a list of them.
+fn decode_null_terms(term: Term) -> NifResult<Vec<NullTerm>> {
+    match term.decode::<Atom>() {
+        Ok(atom) => Ok(vec![NullTerm(atom)]),
+        Err(_) => Ok(term
+            .decode::<Vec<Atom>>()?
+            .into_iter()
+            .map(NullTerm)
+            .collect()),
+    }
+}
+
+/// A non-negative integer, or `:infinity` for no limit.
+fn decode_limit(term: Term) -> NifResult<usize> {
+    match term.decode::<Atom>() {
+        Ok(value) if value == atoms::infinity() => Ok(usize::MAX),
+        _ => term.decode(),
+    }
+}
+
+fn decode_lone_surrogates(term: Term) -> NifResult<LoneSurrogates> {
+    let value: Atom = term.decode()?;
+
+    if value == atoms::reject() {
+        Ok(LoneSurrogates::Reject)
+    } else if value == atoms::replace() {
+        Ok(LoneSurrogates::Replace)
+    } else if value == atoms::wtf8() {
+        Ok(LoneSurrogates::Wtf8)
+    } else {
+        Err(Error::BadArg)
+    }
+}
+
+fn decode_invalid_utf8(term: Term) -> NifResult<InvalidUtf8> {
+    let value: Atom = term.decode()?;
+
+    if value == atoms::reject() {
+        Ok(InvalidUtf8::Reject)
+    } else if value == atoms::replace() {
+        Ok(InvalidUtf8::Replace)
+    } else {
+        Err(Error::BadArg)
+    }
+}
+
+fn decode_duplicates(term: Term) -> NifResult<Duplicates> {
+    let value: Atom = term.decode()?;
+
+    if value == atoms::last() {
+        Ok(Duplicates::Last)
+    } else if value == atoms::first() {
+        Ok(Duplicates::First)
+    } else if value == atoms::reject() {
+        Ok(Duplicates::Reject)
+    } else {
+        Err(Error::BadArg)
+    }
+}
+
+fn decode_objects(term: Term) -> NifResult<Objects> {
+    let value: Atom = term.decode()?;
+
+    if value == atoms::maps() {
+        Ok(Objects::Maps)
+    } else if value == atoms::proplists() {
+        Ok(Objects::Proplists)
+    } else if value == atoms::ejson() {
+        Ok(Objects::Ejson)
+    } else {
+        Err(Error::BadArg)
+    }
+}
+
+fn decode_floats(term: Term) -> NifResult<Floats> {
+    let value: Atom = term.decode()?;
+
+    if value == atoms::native() {
+        Ok(Floats::Native)
+    } else if value == atoms::decimals() {
+        Ok(Floats::Decimals)
+    } else {
+        Err(Error::BadArg)
+    }
+}
+
+fn decode_keys(term: Term) -> NifResult<Keys> {
+    let value: Atom = term.decode()?;
+
+    if value == atoms::strings() {
+        Ok(Keys::Strings)
+    } else if value == atoms::atoms() {
+        Ok(Keys::Atoms)
+    } else if value == atoms::existing_atoms() {
+        Ok(Keys::ExistingAtoms)
+    } else {
+        Err(Error::BadArg)
+    }
+}
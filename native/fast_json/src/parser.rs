@@ -1,137 +1,731 @@
+use std::borrow::Cow;
+
 use crate::errors::*;
-use crate::sink::{TermSink, ValueSink};
+use crate::scan;
+use crate::sink::{NonFinite, Recorder, SinkError, ValueSink};
+use crate::util;
+
+const BACKSPACE: u8 = 8;
+const FORM_FEED: u8 = 12;
+/// Starts each document of an RFC 7464 JSON text sequence.
+const RECORD_SEPARATOR: u8 = 0x1E;
+
+/// What to do with a `\u` escape naming a surrogate that is not part of a
+/// high/low pair.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LoneSurrogates {
+    /// Fail with an error pointing at the escape.
+    #[default]
+    Reject,
+    /// Substitute U+FFFD REPLACEMENT CHARACTER.
+    Replace,
+    /// Pass the surrogate through encoded as WTF-8.
+    Wtf8,
+}
+
+/// What to do with bytes inside a string that are not valid UTF-8.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum InvalidUtf8 {
+    /// Fail with an error pointing at the first invalid byte.
+    #[default]
+    Reject,
+    /// Substitute U+FFFD REPLACEMENT CHARACTER for each maximal invalid
+    /// sequence, as `String::from_utf8_lossy` does.
+    Replace,
+}
 
-const BACKSPACE: char = 8 as char;
-const FORM_FEED: char = 12 as char;
+/// Settings that change what the `Parser` accepts.
+#[derive(Clone, Copy, Debug)]
+pub struct ParserOptions {
+    pub lone_surrogates: LoneSurrogates,
+    pub invalid_utf8: InvalidUtf8,
 
-#[derive(Debug)]
+    /// How deeply objects and arrays may nest before the document is
+    /// rejected, bounding the memory a hostile input can make us hold.
+    pub max_depth: usize,
+
+    /// Limits for untrusted input. `usize::MAX` means no limit.
+    pub max_bytes: usize,
+    /// In bytes, after unescaping. Applies to object keys too.
+    pub max_string_length: usize,
+    /// Per object or array.
+    pub max_elements: usize,
+    /// Counts every digit of a number, including those of the exponent.
+    pub max_number_digits: usize,
+
+    /// Accepts the JSON5 conveniences found in hand-written files: comments,
+    /// trailing commas, single-quoted strings, unquoted keys, hexadecimal
+    /// numbers, and numbers with a leading `+` or `.`.
+    pub lenient: bool,
+
+    /// Accepts `NaN`, `Infinity` and `-Infinity`. Otherwise they fail with
+    /// `NonFiniteNumber`, to tell them apart from mere typos.
+    pub non_finite: bool,
+
+    /// Passes numbers to the sink as they are written, so that they can be
+    /// written back out byte for byte.
+    pub raw_numbers: bool,
+}
+
+impl Default for ParserOptions {
+    fn default() -> ParserOptions {
+        ParserOptions {
+            lone_surrogates: LoneSurrogates::default(),
+            invalid_utf8: InvalidUtf8::default(),
+            max_depth: 1024,
+            max_bytes: usize::MAX,
+            max_string_length: usize::MAX,
+            max_elements: usize::MAX,
+            max_number_digits: usize::MAX,
+            lenient: false,
+            non_finite: false,
+            raw_numbers: false,
+        }
+    }
+}
+
+/// Where an object key or a float starts: an offset into the source, or its
+/// position once the source has been rebased past it.
+#[derive(Clone, Copy, Debug)]
+pub enum KeyAt {
+    Offset(usize),
+    Position(Position),
+}
+
+#[derive(Clone, Debug)]
 enum Stack {
-    Array,
-    Object { key: Option<String> },
+    /// `count` is the number of elements started so far.
+    Array { count: usize },
+    /// `key` is the pending key along with the offset of its opening quote.
+    Object {
+        key: Option<(Vec<u8>, KeyAt)>,
+        count: usize,
+    },
 }
 
-pub struct Parser {
-    /// Source string that we're parsing.
-    s: String,
+pub struct Parser<'a> {
+    /// Source bytes that we're parsing.
+    s: &'a [u8],
 
-    /// Current read position within the string.
+    /// Current read position within the source.
     i: usize,
 
     /// Stack of still-open objects and arrays.
     stack: Vec<Stack>,
+
+    /// Where `s` starts, when it is only the unparsed tail of a stream.
+    origin: Position,
+
+    /// Whether more source may follow `s`. If so, a token that runs into the
+    /// end fails with `UnexpectedEnd` even if it could be complete.
+    partial: bool,
+
+    /// Whether the source is a sequence of documents, so that a complete one
+    /// may be followed by more.
+    sequence: bool,
+
+    options: ParserOptions,
+}
+
+/// The progress of a suspended `Parser`, kept between calls while the source
+/// is not borrowed.
+#[derive(Debug, Default)]
+pub struct ParserState {
+    i: usize,
+    stack: Vec<Stack>,
+    origin: Position,
 }
 
-fn is_whitespace(value: u8) -> bool {
-    match value {
-        b'\t' | b'\r' | b'\n' | b' ' => true,
-        _ => false,
+impl ParserState {
+    /// The state for parsing a source that starts at `origin`.
+    pub fn starting_at(origin: Position) -> ParserState {
+        ParserState {
+            origin,
+            ..ParserState::default()
+        }
+    }
+
+    /// The state for parsing a source from `offset` on.
+    pub fn at_offset(offset: usize) -> ParserState {
+        ParserState {
+            i: offset,
+            ..ParserState::default()
+        }
+    }
+
+    /// How far into the source the parser has got.
+    pub fn offset(&self) -> usize {
+        self.i
+    }
+
+    /// Where the source starts in the whole stream.
+    pub fn origin(&self) -> Position {
+        self.origin
+    }
+
+    /// Accounts for the parsed bytes `consumed` being dropped from the front
+    /// of the source.
+    pub fn rebase(&mut self, consumed: &[u8]) {
+        // Open keys were all parsed before `consumed` ends, outermost first.
+        let (mut position, mut offset) = (self.origin, 0);
+        for entry in &mut self.stack {
+            if let Stack::Object {
+                key: Some((_, at)), ..
+            } = entry
+            {
+                if let KeyAt::Offset(next) = *at {
+                    position = position.advance(&consumed[offset..next]);
+                    offset = next;
+                    *at = KeyAt::Position(position);
+                }
+            }
+        }
+        self.i -= consumed.len();
+        self.origin = self.origin.advance(consumed);
+    }
+}
+
+/// Checks the UTF-8 sequence at the start of `bytes`, which begins with a
+/// non-ASCII byte. Returns its length if valid, or else how many bytes make up
+/// the invalid sequence.
+fn check_utf8(bytes: &[u8]) -> std::result::Result<usize, usize> {
+    let window = &bytes[..bytes.len().min(4)];
+    match std::str::from_utf8(window) {
+        Err(err) if err.valid_up_to() == 0 => Err(err.error_len().unwrap_or(window.len())),
+        _ => Ok(match bytes[0] {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            _ => 4,
+        }),
     }
 }
 
-impl Parser {
-    pub fn new(s: String) -> Parser {
+/// Whether `value` may start an unquoted key. Keys are limited to ASCII
+/// identifiers.
+fn is_identifier_start(value: u8) -> bool {
+    value.is_ascii_alphabetic() || value == b'_' || value == b'$'
+}
+
+fn is_identifier_part(value: u8) -> bool {
+    is_identifier_start(value) || value.is_ascii_digit()
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(s: &'a [u8], options: ParserOptions) -> Parser<'a> {
+        Parser::resume(s, ParserState::default(), options)
+    }
+
+    /// Continues where `suspend` left off. `s` must be the same source.
+    pub fn resume(s: &'a [u8], state: ParserState, options: ParserOptions) -> Parser<'a> {
         Parser {
-            s: s,
-            i: 0,
-            stack: vec![],
+            s,
+            i: state.i,
+            stack: state.stack,
+            origin: state.origin,
+            partial: false,
+            sequence: false,
+            options,
         }
     }
 
+    pub fn suspend(self) -> ParserState {
+        ParserState {
+            i: self.i,
+            stack: self.stack,
+            origin: self.origin,
+        }
+    }
+
+    /// Marks the source as possibly continuing past its end.
+    pub fn partial(mut self, partial: bool) -> Parser<'a> {
+        self.partial = partial;
+        self
+    }
+
+    /// Marks the source as a sequence of documents, separated by whitespace,
+    /// RFC 7464 record separators, or nothing where the documents' own
+    /// delimiters tell them apart.
+    pub fn sequence(mut self, sequence: bool) -> Parser<'a> {
+        self.sequence = sequence;
+        self
+    }
+
     fn push(&mut self, value: Stack) {
         self.stack.push(value);
     }
 
+    /// Called as each object member or array element begins.
+    fn check_elements(&self, count: usize, offset: usize) -> Result<()> {
+        if count > self.options.max_elements {
+            return Err(self.fail_at(Reason::TooManyElements, None, offset));
+        }
+        Ok(())
+    }
+
+    /// Called on `{` or `[`, before the container is opened.
+    fn check_depth(&self) -> Result<()> {
+        if self.stack.len() >= self.options.max_depth {
+            return Err(self.fail_token(Reason::MaxDepthExceeded));
+        }
+        Ok(())
+    }
+
     fn pop(&mut self) -> Option<Stack> {
         self.stack.pop()
     }
 
+    /// Skips whitespace, and comments too when lenient.
     fn skip_ws(&mut self) {
-        while !self.at_end() && is_whitespace(self.peek_next_byte()) {
-            self.i += 1;
+        self.i = scan::skip_whitespace(self.s, self.i);
+        while self.options.lenient {
+            match self.comment_end() {
+                Some(end) => self.i = scan::skip_whitespace(self.s, end),
+                None => break,
+            }
         }
     }
 
-    fn parse_string(&mut self) -> Result<String> {
-        assert_eq!(self.peek_next_byte(), b'"');
-        self.i += 1;
+    /// Returns where the comment at the current position ends, if there is a
+    /// complete one. A line comment may end at the end of the source, unless
+    /// more could follow.
+    fn comment_end(&self) -> Option<usize> {
+        let rest = &self.s[self.i..];
+        if rest.starts_with(b"//") {
+            match rest.iter().position(|&b| b == b'\n') {
+                Some(length) => Some(self.i + length + 1),
+                None if self.partial => None,
+                None => Some(self.s.len()),
+            }
+        } else if rest.starts_with(b"/*") {
+            rest[2..]
+                .windows(2)
+                .position(|pair| pair == b"*/")
+                .map(|length| self.i + 2 + length + 2)
+        } else {
+            None
+        }
+    }
+
+    /// Borrows the string from the source when it has no escapes. Strings
+    /// may be single-quoted when lenient.
+    fn parse_string(&mut self) -> Result<Cow<'a, [u8]>> {
+        let quote = self.peek_next_byte();
+        assert!(quote == b'"' || quote == b'\'' && self.options.lenient);
+        let s = self.s;
         let start = self.i;
-        let mut strval = String::new();
-
-        let mut iter = self.s[start..].char_indices();
-        while let Some((j, c)) = iter.next() {
-            if c == '"' {
-                strval += &self.s[self.i..start + j];
-                self.i = start + j;
-                self.i += 1; // also skip the quote mark itself
-                return Ok(strval);
-            } else if c == '\\' {
-                strval += &self.s[self.i..start + j];
-                self.i = start + j;
-                self.i += 1; // also skip the backslash itself
-                match iter.next() {
-                    None => break,
-                    Some((_, esc_char)) => {
-                        let out_char = match esc_char {
-                            '"' => '"',
-                            '\\' => '\\',
-                            '/' => '/',
-                            'b' => BACKSPACE,
-                            'f' => FORM_FEED,
-                            'n' => '\n',
-                            'r' => '\r',
-                            't' => '\t',
-                            'u' => panic!("oh no not supported yet"),
-                            //                 elif c == 'u':
-                            //                     if self.i + 4 >= len(self.s):
-                            //                         break
-                            //                     hexcode = self.s[self.i:self.i + 4]
-                            //                     for c in hexcode:
-                            //                         if c not in '0123456789abcdefABCDEF':
-                            //                             self.fail("invalid \\u escape")
-                            //                     self.i += 4
-                            //                     strval += chr(int(hexcode, 16))
-                            token => {
-                                return Err(
-                                    self.fail_string(format!("Unexpected token {} in JSON", token))
-                                )
-                            }
-                        };
-                        self.i += 1;
-                        strval.push(out_char);
+        self.i += 1;
+        let mut strval = Vec::new();
+        let mut j = self.i;
+
+        loop {
+            // Everything before `self.i` is in `strval`, and the bytes from
+            // there to `j` are to be copied as they are, so this is the
+            // length so far. Checking as we go keeps a long string from being
+            // unescaped in full before it is rejected.
+            if strval.len() + (j - self.i) > self.options.max_string_length {
+                return Err(self.fail_at(Reason::StringTooLong, None, start));
+            }
+            if j >= s.len() {
+                break;
+            }
+            match s[j] {
+                byte if byte == quote => {
+                    let string = if self.i == start + 1 {
+                        Cow::Borrowed(&s[self.i..j])
+                    } else {
+                        strval.extend_from_slice(&s[self.i..j]);
+                        Cow::Owned(strval)
+                    };
+                    self.i = j + 1; // also skip the quote mark itself
+                    return Ok(string);
+                }
+                b'\\' => {
+                    strval.extend_from_slice(&self.s[self.i..j]);
+                    self.i = j + 1; // also skip the backslash itself
+                    if self.at_end() {
+                        break;
                     }
+                    let out_byte = match self.peek_next_byte() {
+                        b'"' => b'"',
+                        b'\'' if self.options.lenient => b'\'',
+                        b'\\' => b'\\',
+                        b'/' => b'/',
+                        b'b' => BACKSPACE,
+                        b'f' => FORM_FEED,
+                        b'n' => b'\n',
+                        b'r' => b'\r',
+                        b't' => b'\t',
+                        b'u' => {
+                            self.i += 1;
+                            self.parse_unicode_escape(&mut strval)?;
+                            j = self.i;
+                            continue;
+                        }
+                        _ => return Err(self.unexpected()),
+                    };
+                    self.i += 1;
+                    strval.push(out_byte);
+                    j = self.i;
                 }
-            } else if c.is_control() {
-                return Err(self.fail("unexpected control character in string"));
-            } else {
-                // do nothing, we'll copy it into strval later
+                0x00..=0x1f => {
+                    let token = (self.s[j] as char).to_string();
+                    return Err(self.fail_at(Reason::ControlCharacter, Some(token), j));
+                }
+                0x20..=0x7f => {
+                    // do nothing, we'll copy it into strval later
+                    j = if quote == b'"' {
+                        scan::find_string_special(s, j + 1)
+                    } else {
+                        // The scan doesn't stop at single quotes.
+                        j + 1
+                    };
+                }
+                _ => match check_utf8(&s[j..]) {
+                    Ok(len) => j += len,
+                    Err(len) => {
+                        if self.partial && j + len == s.len() {
+                            // Possibly just split across chunks.
+                            return Err(self.fail_at(Reason::UnexpectedEnd, None, s.len()));
+                        }
+                        if self.options.invalid_utf8 == InvalidUtf8::Reject {
+                            return Err(self.fail_at(Reason::InvalidUtf8, None, j));
+                        }
+                        strval.extend_from_slice(&s[self.i..j]);
+                        strval.extend_from_slice("\u{FFFD}".as_bytes());
+                        j += len;
+                        self.i = j;
+                    }
+                },
             }
         }
-        Err(self.fail("Invalid or unexpected token"))
+        Err(self.fail_at(Reason::UnexpectedEnd, None, self.s.len()))
     }
 
-    fn fail(&self, message: &'static str) -> Error {
-        self.fail_string(message.to_string())
+    /// Decodes the escape following a `\u`, combining a high surrogate with an
+    /// immediately following `\u` low surrogate into a single code point.
+    fn parse_unicode_escape(&mut self, strval: &mut Vec<u8>) -> Result<()> {
+        let unit = self.parse_hex_quad()?;
+        let code = match unit {
+            0xD800..=0xDBFF
+                if self.partial
+                    && b"\\u".get(..self.s.len() - self.i) == Some(&self.s[self.i..]) =>
+            {
+                // The low surrogate may be in the next chunk.
+                return Err(self.fail(Reason::UnexpectedEnd));
+            }
+            0xD800..=0xDBFF if self.s[self.i..].starts_with(b"\\u") => {
+                let resume = self.i;
+                self.i += 2;
+                let low = self.parse_hex_quad()?;
+                if (0xDC00..=0xDFFF).contains(&low) {
+                    0x10000 + ((u32::from(unit) - 0xD800) << 10) + (u32::from(low) - 0xDC00)
+                } else {
+                    // Leave the second escape to be decoded on its own.
+                    self.i = resume;
+                    return self.push_lone_surrogate(unit, strval);
+                }
+            }
+            0xD800..=0xDFFF => return self.push_lone_surrogate(unit, strval),
+            _ => u32::from(unit),
+        };
+
+        let mut buf = [0; 4];
+        let c = char::from_u32(code).expect("surrogates are handled above");
+        strval.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        Ok(())
     }
 
-    fn fail_string(&self, message: String) -> Error {
-        ErrorKind::InvalidJson(message.to_string(), self.i).into()
+    fn parse_hex_quad(&mut self) -> Result<u16> {
+        let mut unit = 0;
+        for _ in 0..4 {
+            if self.at_end() {
+                return Err(self.fail(Reason::UnexpectedEnd));
+            }
+            match (self.peek_next_byte() as char).to_digit(16) {
+                Some(digit) => unit = unit * 16 + digit as u16,
+                None => return Err(self.fail_token(Reason::InvalidEscape)),
+            }
+            self.i += 1;
+        }
+        Ok(unit)
     }
 
-    fn parse_key(&mut self) -> Result<String> {
-        self.skip_ws();
-        if self.at_end() || self.peek_next_byte() != b'"' {
-            return Err(self.fail("Unexpected end of JSON input"));
+    fn push_lone_surrogate(&mut self, unit: u16, strval: &mut Vec<u8>) -> Result<()> {
+        match self.options.lone_surrogates {
+            LoneSurrogates::Reject => {
+                // Point at the backslash that started the offending escape.
+                let token = format!("\\u{:X}", unit);
+                Err(self.fail_at(Reason::LoneSurrogate, Some(token), self.i - 6))
+            }
+            LoneSurrogates::Replace => {
+                strval.extend_from_slice("\u{FFFD}".as_bytes());
+                Ok(())
+            }
+            LoneSurrogates::Wtf8 => {
+                // Generalized UTF-8: the surrogate is encoded like any other
+                // three-byte code point.
+                strval.push(0xE0 | (unit >> 12) as u8);
+                strval.push(0x80 | ((unit >> 6) & 0x3F) as u8);
+                strval.push(0x80 | (unit & 0x3F) as u8);
+                Ok(())
+            }
+        }
+    }
+
+    fn fail(&self, reason: Reason) -> Error {
+        self.fail_at(reason, None, self.i)
+    }
+
+    /// Fails with the character at the current position as the token.
+    fn fail_token(&self, reason: Reason) -> Error {
+        let end = self.s.len().min(self.i + 4);
+        let token = String::from_utf8_lossy(&self.s[self.i..end])
+            .chars()
+            .next()
+            .map(String::from);
+        self.fail_at(reason, token, self.i)
+    }
+
+    fn fail_at(&self, reason: Reason, token: Option<String>, offset: usize) -> Error {
+        let position = self.origin.advance(&self.s[..offset]);
+        ErrorKind::InvalidJson(reason, token, position).into()
+    }
+
+    /// Fails on whatever is at the current position, which may be the end.
+    fn unexpected(&self) -> Error {
+        if self.at_end() || self.partial && self.at_literal_prefix() || self.at_open_comment() {
+            self.fail(Reason::UnexpectedEnd)
+        } else {
+            self.fail_token(Reason::UnexpectedToken)
         }
-        let key = self.parse_string()?;
+    }
+
+    /// Whether the rest of the source is a proper prefix of a literal.
+    fn at_literal_prefix(&self) -> bool {
+        let rest = &self.s[self.i..];
+        [
+            &b"true"[..],
+            b"false",
+            b"null",
+            b"NaN",
+            b"Infinity",
+            b"-Infinity",
+            b"+Infinity",
+        ]
+        .iter()
+        .filter(|literal| self.options.lenient || literal[0] != b'+')
+        .any(|literal| rest.len() < literal.len() && literal.starts_with(rest))
+    }
+
+    /// Whether the rest of the source starts a comment that doesn't end in
+    /// it, or that might once more has arrived.
+    fn at_open_comment(&self) -> bool {
+        let rest = &self.s[self.i..];
+        self.options.lenient
+            && self.comment_end().is_none()
+            && (rest.starts_with(b"/*") || self.partial && rest.starts_with(b"/"))
+    }
+
+    fn parse_key(&mut self) -> Result<(Vec<u8>, usize)> {
+        self.skip_ws();
+        let offset = self.i;
+        let lenient = self.options.lenient;
+        let key = match self.s.get(self.i) {
+            Some(b'"') => self.parse_string()?.into_owned(),
+            Some(b'\'') if lenient => self.parse_string()?.into_owned(),
+            Some(&byte) if lenient && is_identifier_start(byte) => self.parse_identifier()?,
+            _ => return Err(self.unexpected()),
+        };
         self.skip_ws();
         if self.at_end() || self.peek_next_byte() != b':' {
-            return Err(self.fail_string(format!(
-                "Unexpected token {} in JSON",
-                self.peek_next_byte() as char
-            )));
+            return Err(self.unexpected());
         }
         self.i += 1;
-        Ok(key)
+        Ok((key, offset))
+    }
+
+    /// Parses an unquoted key.
+    fn parse_identifier(&mut self) -> Result<Vec<u8>> {
+        let start = self.i;
+        while !self.at_end() && is_identifier_part(self.peek_next_byte()) {
+            self.i += 1;
+        }
+        if self.partial && self.at_end() {
+            return Err(self.fail(Reason::UnexpectedEnd));
+        }
+        if self.i - start > self.options.max_string_length {
+            return Err(self.fail_at(Reason::StringTooLong, None, start));
+        }
+        Ok(self.s[start..self.i].to_vec())
+    }
+
+    /// The non-finite number named at the current position, if any, and its
+    /// length. When lenient, `Infinity` may have a `+` sign as numbers may.
+    fn non_finite(&self) -> Option<(NonFinite, usize)> {
+        let rest = &self.s[self.i..];
+        if self.options.lenient && rest.starts_with(b"+Infinity") {
+            return Some((NonFinite::Infinity, "+Infinity".len()));
+        }
+        [
+            NonFinite::NaN,
+            NonFinite::Infinity,
+            NonFinite::NegativeInfinity,
+        ]
+        .iter()
+        .copied()
+        .find(|value| rest.starts_with(value.name().as_bytes()))
+        .map(|value| (value, value.name().len()))
+    }
+
+    fn parse_non_finite<S: ValueSink>(&mut self, sink: &mut S) -> Result<()> {
+        let (value, length) = self.non_finite().unwrap();
+        if !self.options.non_finite {
+            let token = String::from_utf8_lossy(&self.s[self.i..self.i + length]);
+            return Err(self.fail_at(Reason::NonFiniteNumber, Some(token.into_owned()), self.i));
+        }
+        self.i += length;
+        sink.push_non_finite(value);
+        Ok(())
+    }
+
+    fn parse_number<S: ValueSink>(&mut self, sink: &mut S) -> Result<()> {
+        if self.partial && self.at_literal_prefix() {
+            // Possibly the start of `-Infinity`.
+            return Err(self.fail(Reason::UnexpectedEnd));
+        }
+        let start = self.i;
+        let rest = &self.s[start..];
+        let unsigned = rest
+            .strip_prefix(b"-")
+            .or(rest.strip_prefix(b"+"))
+            .unwrap_or(rest);
+        if self.options.lenient && (unsigned.starts_with(b"0x") || unsigned.starts_with(b"0X")) {
+            return self.parse_hex_number(sink);
+        }
+
+        self.scan_number()?;
+        if self.partial && self.at_end() {
+            return Err(self.fail(Reason::UnexpectedEnd));
+        }
+        // Only ASCII was scanned, so this cannot fail.
+        let numstr = std::str::from_utf8(&self.s[start..self.i]).unwrap();
+        let digits = numstr.bytes().filter(u8::is_ascii_digit).count();
+        if digits > self.options.max_number_digits {
+            return Err(self.fail_at(Reason::NumberTooLong, None, start));
+        }
+        if self.options.raw_numbers {
+            sink.push_raw_number(numstr);
+            return Ok(());
+        }
+
+        // A leading `+` is only ever scanned when lenient.
+        let numstr = numstr.strip_prefix('+').unwrap_or(numstr);
+        if numstr.contains('.') || numstr.contains('e') || numstr.contains('E') {
+            if let Err(err) = sink.push_float(numstr, KeyAt::Offset(start)) {
+                let position = self.origin.advance(&self.s[..start]);
+                return Err(sink_error(err, numstr.as_bytes(), position));
+            }
+        } else {
+            match numstr.parse::<i64>() {
+                Ok(number) => sink.push_integer(number),
+                Err(_) => sink.push_big_integer(numstr),
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves past the number at the current position, following the RFC 8259
+    /// grammar: an optional `-`, then `0` or digits not starting with `0`,
+    /// then an optional fraction and exponent. When lenient, the sign may be
+    /// `+` and the integer part may be left out before a fraction.
+    ///
+    /// A sign with no digits after it is an invalid number. Any other missing
+    /// digit is reported where it should have been, so `01` fails at the `1`
+    /// and `1.` at the end of the input.
+    fn scan_number(&mut self) -> Result<()> {
+        let s = self.s;
+        let start = self.i;
+        let digits_at = |i: usize| s[i..].iter().take_while(|b| b.is_ascii_digit()).count();
+        let mut i = start;
+
+        if s[i] == b'-' || s[i] == b'+' {
+            i += 1;
+        }
+        let integer = digits_at(i);
+        // A `.` needs a digit after it to stand in for the integer part.
+        let fraction_only = self.options.lenient
+            && integer == 0
+            && s.get(i) == Some(&b'.')
+            && (digits_at(i + 1) > 0 || self.partial && i + 1 == s.len());
+        if integer == 0 && !fraction_only {
+            if self.partial && i == s.len() {
+                return Err(self.fail_at(Reason::UnexpectedEnd, None, i));
+            }
+            let run = s[start..]
+                .iter()
+                .take_while(|b| b"+-0123456789.eE".contains(b))
+                .count();
+            let token = String::from_utf8_lossy(&s[start..start + run]).into_owned();
+            return Err(self.fail_at(Reason::InvalidNumber, Some(token), start));
+        }
+        // A leading zero is a whole integer part by itself.
+        i += if s[i] == b'0' { 1 } else { integer };
+
+        if s.get(i) == Some(&b'.') {
+            i += 1;
+            i = self.expect_digits(i)?;
+        }
+        if let Some(b'e') | Some(b'E') = s.get(i) {
+            i += 1;
+            if let Some(b'+') | Some(b'-') = s.get(i) {
+                i += 1;
+            }
+            i = self.expect_digits(i)?;
+        }
+        self.i = i;
+        Ok(())
+    }
+
+    /// Returns the end of the run of digits at `i`, failing if it is empty.
+    fn expect_digits(&mut self, i: usize) -> Result<usize> {
+        let count = self.s[i..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        if count == 0 {
+            self.i = i;
+            return Err(self.unexpected());
+        }
+        Ok(i + count)
+    }
+
+    /// Parses a lenient hexadecimal integer such as `-0x1F`.
+    fn parse_hex_number<S: ValueSink>(&mut self, sink: &mut S) -> Result<()> {
+        let start = self.i;
+        while !self.at_end() && b"+-0123456789abcdefABCDEFxX".contains(&self.peek_next_byte()) {
+            self.i += 1;
+        }
+        if self.partial && self.at_end() {
+            return Err(self.fail(Reason::UnexpectedEnd));
+        }
+        // Only ASCII was scanned, so this cannot fail.
+        let numstr = std::str::from_utf8(&self.s[start..self.i]).unwrap();
+        // Not counting the `0` of the `0x`.
+        let digits = numstr.bytes().filter(u8::is_ascii_hexdigit).count() - 1;
+        if digits > self.options.max_number_digits {
+            return Err(self.fail_at(Reason::NumberTooLong, None, start));
+        }
+        let invalid = || self.fail_at(Reason::InvalidNumber, Some(numstr.to_string()), start);
+        let number = parse_hex(numstr).ok_or_else(invalid)?;
+        if self.options.raw_numbers {
+            sink.push_raw_number(numstr);
+            return Ok(());
+        }
+        match number.parse::<i64>() {
+            Ok(number) => sink.push_integer(number),
+            Err(_) => sink.push_big_integer(&number),
+        }
+        Ok(())
     }
 
     fn at_end(&self) -> bool {
@@ -139,138 +733,161 @@ impl Parser {
     }
 
     fn peek_next_byte(&self) -> u8 {
-        self.s.as_bytes()[self.i]
+        self.s[self.i]
     }
 
-    fn parse_one_value(&mut self, sink: &mut TermSink) -> Result<()> {
+    fn parse_one_value<S: ValueSink>(&mut self, sink: &mut S) -> Result<()> {
         loop {
             self.skip_ws();
             if self.at_end() {
-                let msg = match self.stack.last() {
-                    _ => "Unexpected end of JSON input",
-                };
-                return Err(self.fail(msg));
+                return Err(self.fail(Reason::UnexpectedEnd));
             }
 
-            let value = match self.peek_next_byte() {
-                b'-' | b'0'..=b'9' => {
-                    let start = self.i;
-                    while !self.at_end() && b"+-0123456789.eE".contains(&self.peek_next_byte()) {
-                        self.i += 1;
-                    }
-                    let numstr = &self.s[start..self.i];
-                    if numstr.contains('.') || numstr.contains('e') || numstr.contains("E") {
-                        let number: f64 = numstr
-                            .parse()
-                            .chain_err(|| self.fail("Unexpected number in JSON"))?;
-                        sink.push_float(number);
-                    } else {
-                        let number: i64 = numstr
-                            .parse()
-                            .chain_err(|| self.fail("Unexpected number in JSON"))?;
-                        sink.push_integer(number);
-                    }
+            if self.peek_next_byte() != b']' {
+                if let Some(Stack::Array { count }) = self.stack.last_mut() {
+                    *count += 1;
+                    let count = *count;
+                    self.check_elements(count, self.i)?;
                 }
+            }
 
-                b'"' => {
-                    sink.push_string(self.parse_string()?);
+            match self.peek_next_byte() {
+                b'N' | b'I' | b'-' | b'+' if self.non_finite().is_some() => {
+                    self.parse_non_finite(sink)?
                 }
+                b'-' | b'0'..=b'9' => self.parse_number(sink)?,
+                b'+' | b'.' if self.options.lenient => self.parse_number(sink)?,
 
-                b't' if self.s[self.i..].starts_with("true") => {
+                b'\'' if !self.options.lenient => return Err(self.unexpected()),
+                b'"' | b'\'' => {
+                    let offset = self.i + 1;
+                    match self.parse_string()? {
+                        Cow::Borrowed(string) => sink.push_source_string(string, offset),
+                        Cow::Owned(string) => sink.push_string(&string),
+                    }
+                }
+
+                b't' if self.s[self.i..].starts_with(b"true") => {
                     self.i += 4;
                     sink.push_bool(true);
                 }
-                b'f' if self.s[self.i..].starts_with("false") => {
+                b'f' if self.s[self.i..].starts_with(b"false") => {
                     self.i += 5;
                     sink.push_bool(false);
                 }
-                b'n' if self.s[self.i..].starts_with("null") => {
+                b'n' if self.s[self.i..].starts_with(b"null") => {
                     self.i += 4;
                     sink.push_nil();
                 }
 
                 b'{' => {
+                    self.check_depth()?;
                     self.i += 1;
                     self.skip_ws();
                     if !self.at_end() && self.peek_next_byte() == b'}' {
                         self.i += 1;
                         sink.push_map();
+                        sink.finalize_map();
                     } else {
-                        let key = self.parse_key()?;
+                        let (key, offset) = self.parse_key()?;
+                        self.check_elements(1, offset)?;
                         sink.push_map(); // should not call pop_insert_*
-                        self.push(Stack::Object { key: Some(key) });
+                        self.push(Stack::Object {
+                            key: Some((key, KeyAt::Offset(offset))),
+                            count: 1,
+                        });
                         continue;
                     }
                 }
 
                 b'}' => {
-                    self.i += 1;
+                    // A key still pending here is missing its value.
                     match self.pop() {
-                        Some(Stack::Object { key }) => {
-                            assert!(key.is_none());
-                            sink.finalize_map();
-                        }
-                        _ => return Err(self.fail("found '}' without matching '{'")),
+                        Some(Stack::Object { key: None, .. }) => sink.finalize_map(),
+                        _ => return Err(self.unexpected()),
                     }
+                    self.i += 1;
                 }
 
                 b'[' => {
+                    self.check_depth()?;
                     self.i += 1;
                     sink.push_array();
-                    self.push(Stack::Array);
+                    self.push(Stack::Array { count: 0 });
                     continue;
                 }
 
                 b']' => {
-                    self.i += 1;
                     match self.pop() {
-                        Some(Stack::Array) => sink.finalize_array(),
-                        _ => return Err(self.fail("found ']' without matching '['")),
+                        Some(Stack::Array { .. }) => sink.finalize_array(),
+                        _ => return Err(self.unexpected()),
                     }
+                    self.i += 1;
                 }
 
-                token => {
-                    return Err(self.fail_string(format!("Unexpected token {}", token as char)))
-                }
+                _ => return Err(self.unexpected()),
             };
-            return Ok(value);
+            return Ok(());
         }
     }
 
-    fn store_value(&mut self, sink: &mut TermSink) -> Result<()> {
+    fn store_value<S: ValueSink>(&mut self, sink: &mut S) -> Result<()> {
         match self.stack.pop() {
-            Some(Stack::Object { key }) => {
-                sink.pop_insert_map(key.unwrap());
+            Some(Stack::Object { key, count }) => {
+                let (key, at) = key.unwrap();
+                if let Err(err) = sink.pop_insert_map(&key, at) {
+                    let position = match at {
+                        KeyAt::Offset(offset) => self.origin.advance(&self.s[..offset]),
+                        KeyAt::Position(position) => position,
+                    };
+                    return Err(sink_error(err, &key, position));
+                }
                 self.skip_ws();
                 if self.at_end() {
-                    return Err(self.fail("unmatched '{'"));
+                    return Err(self.fail(Reason::UnexpectedEnd));
                 }
                 match self.peek_next_byte() {
                     b',' => {
                         self.i += 1;
-                        let new_key = self.parse_key()?;
-                        self.stack.push(Stack::Object { key: Some(new_key) });
+                        self.skip_ws();
+                        if self.options.lenient && self.s.get(self.i) == Some(&b'}') {
+                            self.stack.push(Stack::Object { key: None, count });
+                            return Ok(());
+                        }
+                        let (new_key, offset) = self.parse_key()?;
+                        self.check_elements(count + 1, offset)?;
+                        self.stack.push(Stack::Object {
+                            key: Some((new_key, KeyAt::Offset(offset))),
+                            count: count + 1,
+                        });
                     }
                     b'}' => {
-                        self.stack.push(Stack::Object { key: None });
-                    }
-                    _ => {
-                        return Err(self.fail("expected ',' or '}' after key-value pair in object"));
+                        self.stack.push(Stack::Object { key: None, count });
                     }
+                    _ => return Err(self.unexpected()),
                 }
             }
-            Some(Stack::Array) => {
+            Some(Stack::Array { count }) => {
                 sink.pop_insert_array();
                 self.skip_ws();
                 if self.at_end() {
-                    return Err(self.fail("unmatched '['"));
+                    return Err(self.fail(Reason::UnexpectedEnd));
                 }
                 match self.peek_next_byte() {
-                    b',' => self.i += 1,
+                    b',' => {
+                        self.i += 1;
+                        self.skip_ws();
+                        if self.at_end() {
+                            return Err(self.fail(Reason::UnexpectedEnd));
+                        }
+                        if self.peek_next_byte() == b']' && !self.options.lenient {
+                            return Err(self.unexpected());
+                        }
+                    }
                     b']' => {}
-                    _ => return Err(self.fail("expected ',' or ']' after array element")),
+                    _ => return Err(self.unexpected()),
                 }
-                self.stack.push(Stack::Array);
+                self.stack.push(Stack::Array { count });
             }
             None => panic!("can't happen"),
         }
@@ -279,21 +896,734 @@ impl Parser {
 
     fn finish(&mut self) -> Result<()> {
         self.skip_ws();
-        if self.i < self.s.len() {
-            return Err(self.fail("unexpected extra data after JSON"));
+        if !self.at_end() {
+            return Err(self.unexpected());
         }
         Ok(())
     }
 
-    pub fn parse(&mut self, sink: &mut TermSink) -> Result<bool> {
+    /// Checks that a document in a sequence that doesn't end in a delimiter
+    /// of its own, a number or a literal, is followed by whitespace, a record
+    /// separator, a comment when lenient, or the end. Otherwise `01` would be
+    /// two documents.
+    fn check_separated(&self) -> Result<()> {
+        if let b'}' | b']' | b'"' | b'\'' = self.s[self.i - 1] {
+            return Ok(());
+        }
+        match self.s.get(self.i) {
+            None | Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') => Ok(()),
+            Some(&RECORD_SEPARATOR) => Ok(()),
+            Some(b'/') if self.options.lenient => Ok(()),
+            Some(_) => Err(self.unexpected()),
+        }
+    }
+
+    /// Parses a single value into `sink`. Returns `Ok(true)` once the
+    /// document is complete, leaving the root value as the sink's only
+    /// entry, and `Ok(false)` when there is more to do.
+    pub fn parse<S: ValueSink>(&mut self, sink: &mut S) -> Result<bool> {
+        if self.origin.offset + self.s.len() > self.options.max_bytes {
+            // A resumed source may start past the limit already.
+            let offset = self.options.max_bytes.saturating_sub(self.origin.offset);
+            return Err(self.fail_at(Reason::InputTooLarge, None, offset));
+        }
+
         self.parse_one_value(sink)?;
 
         if self.stack.is_empty() {
-            self.finish()?;
+            if self.sequence {
+                self.check_separated()?;
+            } else {
+                self.finish()?;
+            }
             Ok(true)
         } else {
             self.store_value(sink)?;
             Ok(false)
         }
     }
+
+    /// Like `parse`, for a partial source. A step that runs into the end of
+    /// the source is undone, so that it can be retried once more has arrived,
+    /// and `Ok(None)` returned.
+    pub fn parse_partial(&mut self, sink: &mut Recorder) -> Result<Option<bool>> {
+        // A step pops at most two entries, so only those need saving.
+        let (i, events) = (self.i, sink.len());
+        let kept = self.stack.len().saturating_sub(2);
+        let top = self.stack[kept..].to_vec();
+
+        match self.parse(sink) {
+            Err(ref err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::InvalidJson(Reason::UnexpectedEnd, ..)
+                ) =>
+            {
+                self.i = i;
+                self.stack.truncate(kept);
+                self.stack.extend(top);
+                sink.truncate(events);
+                Ok(None)
+            }
+            result => result.map(Some),
+        }
+    }
+
+    /// Skips whitespace, and comments when lenient. Returns whether that was
+    /// all the rest of the source held.
+    pub fn skip_blank(&mut self) -> bool {
+        self.skip_ws();
+        self.at_end()
+    }
+
+    /// Skips to the start of the next document in a sequence. Returns
+    /// whether there is one.
+    pub fn next_document(&mut self) -> bool {
+        self.skip_ws();
+        while !self.at_end() && self.peek_next_byte() == RECORD_SEPARATOR {
+            self.i += 1;
+            self.skip_ws();
+        }
+        !self.at_end()
+    }
+
+    /// How far into the source the parser has got.
+    pub fn offset(&self) -> usize {
+        self.i
+    }
+
+    /// Checks that nothing but whitespace follows a complete document.
+    pub fn parse_trailing(&mut self) -> Result<()> {
+        match self.finish() {
+            // A comment still to be closed, which is left unconsumed.
+            Err(ref err)
+                if self.partial
+                    && matches!(
+                        err.kind(),
+                        ErrorKind::InvalidJson(Reason::UnexpectedEnd, ..)
+                    ) =>
+            {
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+/// Converts a hexadecimal integer, such as `-0x1F`, to decimal digits, as
+/// taken by `ValueSink::push_big_integer`.
+fn parse_hex(numstr: &str) -> Option<String> {
+    let (negative, unsigned) = match numstr.as_bytes()[0] {
+        b'-' => (true, &numstr[1..]),
+        b'+' => (false, &numstr[1..]),
+        _ => (false, numstr),
+    };
+    let digits = &unsigned[2..];
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    // Little-endian base 256, two hex digits to a byte.
+    let magnitude: Vec<u8> = digits
+        .as_bytes()
+        .rchunks(2)
+        .map(|pair| pair.iter().fold(0, |byte, &b| byte << 4 | hex_value(b)))
+        .collect();
+    let digits = util::magnitude_digits(&magnitude);
+    if negative && digits != "0" {
+        Some(format!("-{}", digits))
+    } else {
+        Some(digits)
+    }
+}
+
+fn hex_value(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        _ => (digit | 0x20) - b'a' + 10,
+    }
+}
+
+/// Whether `text` is a single number in the strict grammar, with nothing
+/// around it.
+pub fn is_number(text: &[u8]) -> bool {
+    let mut parser = Parser::new(text, ParserOptions::default());
+    match text.first() {
+        Some(b'-') | Some(b'0'..=b'9') => parser.scan_number().is_ok() && parser.at_end(),
+        _ => false,
+    }
+}
+
+/// Describes a key or a float that the sink refused.
+pub fn sink_error(err: SinkError, token: &[u8], position: Position) -> Error {
+    let reason = match err {
+        SinkError::UnknownAtom => Reason::UnknownAtom,
+        SinkError::InvalidAtom => Reason::InvalidAtom,
+        SinkError::DuplicateKey => Reason::DuplicateKey,
+        SinkError::FloatOverflow => Reason::InvalidNumber,
+    };
+    let token = String::from_utf8_lossy(token).into_owned();
+    ErrorKind::InvalidJson(reason, Some(token), position).into()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Renders values back out as compact JSON-ish text, so tests can drive
+    /// the parser without a BEAM environment.
+    #[derive(Default)]
+    pub(crate) struct TextSink {
+        pub(crate) stack: Vec<(String, usize)>,
+    }
+
+    impl TextSink {
+        fn push(&mut self, text: String) {
+            self.stack.push((text, 0));
+        }
+
+        fn insert(&mut self, entry: String) {
+            let (mut text, count) = self.stack.pop().unwrap();
+            if count > 0 {
+                text.push(',');
+            }
+            text += &entry;
+            self.stack.push((text, count + 1));
+        }
+    }
+
+    impl ValueSink for TextSink {
+        fn push_map(&mut self) {
+            self.push("{".to_string());
+        }
+        fn push_array(&mut self) {
+            self.push("[".to_string());
+        }
+        fn push_string(&mut self, string: &[u8]) {
+            self.push(format!("{:?}", std::str::from_utf8(string).unwrap()));
+        }
+        fn push_integer(&mut self, integer: i64) {
+            self.push(integer.to_string());
+        }
+        fn push_big_integer(&mut self, digits: &str) {
+            self.push(digits.to_string());
+        }
+        fn push_float(&mut self, text: &str, _at: KeyAt) -> std::result::Result<(), SinkError> {
+            self.push(format!("{:?}", text.parse::<f64>().unwrap()));
+            Ok(())
+        }
+        fn push_non_finite(&mut self, value: NonFinite) {
+            self.push(value.name().to_string());
+        }
+        fn push_raw_number(&mut self, text: &str) {
+            self.push(format!("<{}>", text));
+        }
+        fn push_bool(&mut self, boolean: bool) {
+            self.push(boolean.to_string());
+        }
+        fn push_nil(&mut self) {
+            self.push("null".to_string());
+        }
+        fn finalize_map(&mut self) {
+            self.stack.last_mut().unwrap().0.push('}');
+        }
+        fn finalize_array(&mut self) {
+            self.stack.last_mut().unwrap().0.push(']');
+        }
+        fn pop_insert_map(&mut self, key: &[u8], _at: KeyAt) -> std::result::Result<(), SinkError> {
+            let (value, _) = self.stack.pop().unwrap();
+            self.insert(format!("{:?}:{}", String::from_utf8_lossy(key), value));
+            Ok(())
+        }
+        fn pop_insert_array(&mut self) {
+            let (value, _) = self.stack.pop().unwrap();
+            self.insert(value);
+        }
+    }
+
+    pub(crate) fn parse(input: &str) -> Result<String> {
+        let mut parser = Parser::new(input.as_bytes(), ParserOptions::default());
+        let mut sink = TextSink::default();
+        while !parser.parse(&mut sink)? {}
+        assert_eq!(sink.stack.len(), 1);
+        Ok(sink.stack.pop().unwrap().0)
+    }
+
+    #[test]
+    fn parses_nested_values() {
+        assert_eq!(
+            parse(r#" {"a": [1, 2.5, "x\u00e9"], "b": {"c": null}, "d": true} "#).unwrap(),
+            r#"{"a":[1,2.5,"xé"],"b":{"c":null},"d":true}"#
+        );
+        assert_eq!(parse("[[], {}]").unwrap(), "[[],{}]");
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        let options = ParserOptions {
+            max_depth: 3,
+            ..ParserOptions::default()
+        };
+        let parse = |input: &str| -> Result<()> {
+            let mut parser = Parser::new(input.as_bytes(), options);
+            let mut sink = TextSink::default();
+            while !parser.parse(&mut sink)? {}
+            Ok(())
+        };
+
+        assert!(parse("[[{}]]").is_ok());
+        assert!(parse(r#"{"a": [{"b": 1}]}"#).is_ok());
+        assert!(parse("[[[[]]]]").is_err());
+        assert!(parse(r#"[{"a": {}}]"#).is_ok());
+        assert!(parse(r#"[{"a": [{}]}]"#).is_err());
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let parse = |input: &str, options: ParserOptions| -> Option<(Reason, usize)> {
+            let mut parser = Parser::new(input.as_bytes(), options);
+            let mut sink = TextSink::default();
+            loop {
+                match parser.parse(&mut sink) {
+                    Ok(true) => return None,
+                    Ok(false) => continue,
+                    Err(err) => match err.kind() {
+                        ErrorKind::InvalidJson(reason, _, position) => {
+                            return Some((*reason, position.offset))
+                        }
+                        kind => panic!("unexpected error {:?}", kind),
+                    },
+                }
+            }
+        };
+        let defaults = ParserOptions::default();
+
+        let options = ParserOptions {
+            max_bytes: 4,
+            ..defaults
+        };
+        assert_eq!(parse("[12]", options), None);
+        assert_eq!(parse("[123]", options), Some((Reason::InputTooLarge, 4)));
+        let origin = Position {
+            offset: 6,
+            line: 2,
+            column: 1,
+        };
+        let mut parser = Parser::resume(b"2", ParserState::starting_at(origin), options);
+        match parser.parse(&mut TextSink::default()).unwrap_err().kind() {
+            ErrorKind::InvalidJson(Reason::InputTooLarge, _, position) => {
+                assert_eq!(*position, origin)
+            }
+            kind => panic!("unexpected error {:?}", kind),
+        }
+
+        let options = ParserOptions {
+            max_string_length: 2,
+            ..defaults
+        };
+        assert_eq!(parse(r#"{"ab": "\u00e9"}"#, options), None);
+        assert_eq!(
+            parse(r#"["abc"]"#, options),
+            Some((Reason::StringTooLong, 1))
+        );
+        assert_eq!(
+            parse(r#"{"abc": 1}"#, options),
+            Some((Reason::StringTooLong, 1))
+        );
+        // Found before the end, which an unterminated string never reaches.
+        assert_eq!(
+            parse(r#"["\u00e9\u00e9"#, options),
+            Some((Reason::StringTooLong, 1))
+        );
+        assert_eq!(
+            parse(r#"["\n\n\n"#, options),
+            Some((Reason::StringTooLong, 1))
+        );
+        assert_eq!(
+            parse(r#"["abcdef"#, options),
+            Some((Reason::StringTooLong, 1))
+        );
+
+        let options = ParserOptions {
+            max_elements: 2,
+            ..defaults
+        };
+        assert_eq!(
+            parse("[[1, 2], [], {}]", options),
+            Some((Reason::TooManyElements, 13))
+        );
+        assert_eq!(parse(r#"[{"a": 1, "b": [3, 4]}, 2]"#, options), None);
+        assert_eq!(
+            parse(r#"{"a": 1, "b": 2, "c": 3}"#, options),
+            Some((Reason::TooManyElements, 17))
+        );
+
+        let options = ParserOptions {
+            max_elements: 0,
+            ..defaults
+        };
+        assert_eq!(
+            parse("[[], {}]", options),
+            Some((Reason::TooManyElements, 1))
+        );
+        assert_eq!(
+            parse(r#"{"a": 1}"#, options),
+            Some((Reason::TooManyElements, 1))
+        );
+
+        let options = ParserOptions {
+            max_number_digits: 3,
+            ..defaults
+        };
+        assert_eq!(parse("[-123, 1.5e1]", options), None);
+        assert_eq!(
+            parse("[1, -1234]", options),
+            Some((Reason::NumberTooLong, 4))
+        );
+        assert_eq!(parse("1.5e10", options), Some((Reason::NumberTooLong, 0)));
+    }
+
+    #[test]
+    fn validates_utf8_in_strings() {
+        let parse = |input: &[u8], invalid_utf8| -> Result<String> {
+            let options = ParserOptions {
+                invalid_utf8,
+                ..ParserOptions::default()
+            };
+            let mut parser = Parser::new(input, options);
+            let mut sink = TextSink::default();
+            while !parser.parse(&mut sink)? {}
+            Ok(sink.stack.pop().unwrap().0)
+        };
+        let offset = |input: &[u8]| match parse(input, InvalidUtf8::Reject) {
+            Err(err) => match err.kind() {
+                ErrorKind::InvalidJson(Reason::InvalidUtf8, None, position) => position.offset,
+                kind => panic!("unexpected error {:?}", kind),
+            },
+            Ok(text) => panic!("unexpected success {}", text),
+        };
+
+        assert_eq!(
+            parse(
+                "[\"\u{e9}\u{2603}\u{1F600}\"]".as_bytes(),
+                InvalidUtf8::Reject
+            )
+            .unwrap(),
+            "[\"\u{e9}\u{2603}\u{1F600}\"]"
+        );
+        assert_eq!(offset(b"\"\x80\""), 1);
+        assert_eq!(offset(b"[\"ab\xC3\"]"), 4);
+        assert_eq!(offset(b"[\"\xC0\xAF\"]"), 2); // overlong
+        assert_eq!(offset(b"{\"\xED\xA0\x80\": 1}"), 2); // surrogate
+        assert_eq!(offset(b"\"\xE2\x98\xF0\x9F\x98\x80\""), 1);
+
+        assert_eq!(
+            parse(b"[\"a\xE2\x98b\xFF\\n\"]", InvalidUtf8::Replace).unwrap(),
+            "[\"a\u{FFFD}b\u{FFFD}\\n\"]"
+        );
+    }
+
+    #[test]
+    fn parses_lenient_syntax() {
+        let parse = |input: &str, lenient: bool| -> Result<String> {
+            let options = ParserOptions {
+                lenient,
+                ..ParserOptions::default()
+            };
+            let mut parser = Parser::new(input.as_bytes(), options);
+            let mut sink = TextSink::default();
+            while !parser.parse(&mut sink)? {}
+            Ok(sink.stack.pop().unwrap().0)
+        };
+
+        let input = r#"
+            // A config file.
+            {
+                name: 'it\'s "quoted"', /* inline */ $id_2: 0x1F,
+                "list": [+1, .5, -0XfF, +.25, 'x',],
+                nested: {a: [],},
+            } // done"#;
+        assert_eq!(
+            parse(input, true).unwrap(),
+            r#"{"name":"it's \"quoted\"","$id_2":31,"list":[1,0.5,-255,0.25,"x"],"nested":{"a":[]}}"#
+        );
+        assert_eq!(
+            parse("0x7FFFFFFFFFFFFFFFFF", true).unwrap(),
+            "2361183241434822606847"
+        );
+        assert_eq!(parse("/**/1/***/", true).unwrap(), "1");
+        assert_eq!(
+            parse(&format!("0x{}", "F".repeat(40)), true).unwrap(),
+            "1461501637330902918203684832716283019655932542975"
+        );
+        assert_eq!(
+            parse(&format!("-0x1{}", "0".repeat(32)), true).unwrap(),
+            "-340282366920938463463374607431768211456"
+        );
+        assert_eq!(parse("[-0x0, 0x00ab]", true).unwrap(), "[0,171]");
+
+        // A `.` alone is not a number.
+        for (input, token, offset) in [
+            (".", ".", 0),
+            ("[.]", ".", 1),
+            ("-.", "-.", 0),
+            ("+.e1", "+.e1", 0),
+        ] {
+            match parse(input, true).unwrap_err().kind() {
+                ErrorKind::InvalidJson(Reason::InvalidNumber, found, position) => {
+                    assert_eq!((found.as_deref(), position.offset), (Some(token), offset))
+                }
+                kind => panic!("unexpected error {:?} for {}", kind, input),
+            }
+        }
+
+        for input in [
+            "[1,]",
+            "{\"a\":1,}",
+            "// c\n1",
+            "'a'",
+            "{a:1}",
+            "0x1",
+            "+1",
+            ".5",
+        ] {
+            assert!(parse(input, false).is_err(), "{}", input);
+        }
+        for input in [
+            "[,]", "[1,,]", "{,}", "+-1", "++1", "0x", "0xG", "1 /* x", "/ 1", "{1a: 2}",
+        ] {
+            assert!(parse(input, true).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn parses_non_finite_numbers_when_asked() {
+        let options = ParserOptions {
+            non_finite: true,
+            ..ParserOptions::default()
+        };
+        let mut parser = Parser::new(b"[NaN, Infinity, -Infinity, -1]", options);
+        let mut sink = TextSink::default();
+        while !parser.parse(&mut sink).unwrap() {}
+        assert_eq!(sink.stack.pop().unwrap().0, "[NaN,Infinity,-Infinity,-1]");
+
+        let (reason, token, position) = error("[1, -Infinity]");
+        assert_eq!(
+            (reason, token.as_deref()),
+            (Reason::NonFiniteNumber, Some("-Infinity"))
+        );
+        assert_eq!(position.offset, 4);
+        assert_eq!(error("NaN").0, Reason::NonFiniteNumber);
+        assert_eq!(error("Inf").0, Reason::UnexpectedToken);
+        assert_eq!(error("-Inf").0, Reason::InvalidNumber);
+
+        // Lenient numbers may have a `+`, `Infinity` included.
+        let options = ParserOptions {
+            lenient: true,
+            ..options
+        };
+        let mut parser = Parser::new(b"[+Infinity, +1]", options);
+        let mut sink = TextSink::default();
+        while !parser.parse(&mut sink).unwrap() {}
+        assert_eq!(sink.stack.pop().unwrap().0, "[Infinity,1]");
+        let options = ParserOptions {
+            non_finite: false,
+            ..options
+        };
+        let mut parser = Parser::new(b"+Infinity", options);
+        match parser.parse(&mut TextSink::default()).unwrap_err().kind() {
+            ErrorKind::InvalidJson(Reason::NonFiniteNumber, token, _) => {
+                assert_eq!(token.as_deref(), Some("+Infinity"))
+            }
+            kind => panic!("unexpected error {:?}", kind),
+        }
+        assert_eq!(error("+Infinity").0, Reason::UnexpectedToken);
+    }
+
+    #[test]
+    fn follows_the_number_grammar() {
+        for input in [
+            "0",
+            "-0",
+            "0.0",
+            "10",
+            "-1.25e-3",
+            "1E+2",
+            "0e0",
+            "123.456E789",
+        ] {
+            assert!(parse(input).is_ok(), "{}", input);
+        }
+
+        let at = |input: &str| {
+            let (reason, token, position) = error(input);
+            (reason, token, position.offset)
+        };
+        let token = |text: &str| Some(text.to_string());
+        assert_eq!(at("01"), (Reason::UnexpectedToken, token("1"), 1));
+        assert_eq!(at("-01"), (Reason::UnexpectedToken, token("1"), 2));
+        assert_eq!(at("[00]"), (Reason::UnexpectedToken, token("0"), 2));
+        assert_eq!(at("1."), (Reason::UnexpectedEnd, None, 2));
+        assert_eq!(at("[1.]"), (Reason::UnexpectedToken, token("]"), 3));
+        assert_eq!(at("1.e5"), (Reason::UnexpectedToken, token("e"), 2));
+        assert_eq!(at("1e"), (Reason::UnexpectedEnd, None, 2));
+        assert_eq!(at("1.0e+"), (Reason::UnexpectedEnd, None, 5));
+        assert_eq!(at("[1e-x]"), (Reason::UnexpectedToken, token("x"), 4));
+        assert_eq!(at("1.5.3"), (Reason::UnexpectedToken, token("."), 3));
+        assert_eq!(at("1-2"), (Reason::UnexpectedToken, token("-"), 1));
+        assert_eq!(at("-"), (Reason::InvalidNumber, token("-"), 0));
+        assert_eq!(at("[-]"), (Reason::InvalidNumber, token("-"), 1));
+        assert_eq!(at("--1"), (Reason::InvalidNumber, token("--1"), 0));
+        assert_eq!(at("-.5"), (Reason::InvalidNumber, token("-.5"), 0));
+    }
+
+    #[test]
+    fn passes_raw_numbers_through() {
+        let parse = |input: &str, lenient: bool| -> Result<String> {
+            let options = ParserOptions {
+                raw_numbers: true,
+                lenient,
+                ..ParserOptions::default()
+            };
+            let mut parser = Parser::new(input.as_bytes(), options);
+            let mut sink = TextSink::default();
+            while !parser.parse(&mut sink)? {}
+            Ok(sink.stack.pop().unwrap().0)
+        };
+        assert_eq!(
+            parse("[1.000, -0, 1E+2, 123456789012345678901234567890]", false).unwrap(),
+            "[<1.000>,<-0>,<1E+2>,<123456789012345678901234567890>]"
+        );
+        assert_eq!(parse("[+.5, -0x1f]", true).unwrap(), "[<+.5>,<-0x1f>]");
+        assert!(parse("01", false).is_err());
+
+        for text in [
+            "0",
+            "-0",
+            "1.000",
+            "1E+2",
+            "-12.5e-3",
+            "123456789012345678901234567890",
+        ] {
+            assert!(is_number(text.as_bytes()), "{}", text);
+        }
+        for text in [
+            "", "-", "01", "1.", ".5", "+1", "0x1", " 1", "1 ", "1,2", "NaN", "1e",
+        ] {
+            assert!(!is_number(text.as_bytes()), "{}", text);
+        }
+    }
+
+    #[test]
+    fn passes_big_integers_through_as_digits() {
+        assert_eq!(
+            parse("[9223372036854775807, -9223372036854775808, 18446744073709551616]").unwrap(),
+            "[9223372036854775807,-9223372036854775808,18446744073709551616]"
+        );
+        assert!(parse("99999999999999999999-1").is_err());
+    }
+
+    #[test]
+    fn parses_sequences() {
+        let sequence = |input: &str| -> Result<Vec<(String, usize)>> {
+            let mut documents = vec![];
+            let mut state = ParserState::default();
+            loop {
+                let mut parser = Parser::resume(input.as_bytes(), state, ParserOptions::default())
+                    .sequence(true);
+                if !parser.next_document() {
+                    return Ok(documents);
+                }
+                let mut sink = TextSink::default();
+                while !parser.parse(&mut sink)? {}
+                documents.push((sink.stack.pop().unwrap().0, parser.offset()));
+                state = ParserState::at_offset(parser.offset());
+            }
+        };
+
+        assert_eq!(
+            sequence("{\"a\":1}{\"b\":2} [3]\n\"x\"true\x1enull 4").unwrap(),
+            vec![
+                ("{\"a\":1}".to_string(), 7),
+                ("{\"b\":2}".to_string(), 14),
+                ("[3]".to_string(), 18),
+                ("\"x\"".to_string(), 22),
+                ("true".to_string(), 26),
+                ("null".to_string(), 31),
+                ("4".to_string(), 33),
+            ]
+        );
+        assert_eq!(
+            sequence("\x1e{\"a\":1}\n\x1e\x1e 2\n").unwrap(),
+            vec![("{\"a\":1}".to_string(), 8), ("2".to_string(), 13)]
+        );
+        assert_eq!(sequence(" \x1e\n").unwrap(), vec![]);
+        assert!(sequence("[1]]").is_err());
+        assert!(sequence("[1,\x1e2]").is_err());
+        assert!(sequence("1 2").is_ok() && parse("1 2").is_err());
+        assert_eq!(
+            sequence("1\x1e2\t3").unwrap(),
+            vec![
+                ("1".to_string(), 1),
+                ("2".to_string(), 3),
+                ("3".to_string(), 5)
+            ]
+        );
+
+        // Numbers and literals need something after them to end them.
+        for (input, offset) in [
+            ("01", 1),
+            ("-0-0", 2),
+            ("truenull", 4),
+            ("1[2]", 1),
+            ("null\"x\"", 4),
+        ] {
+            match sequence(input).unwrap_err().kind() {
+                ErrorKind::InvalidJson(Reason::UnexpectedToken, _, position) => {
+                    assert_eq!(position.offset, offset, "{}", input)
+                }
+                kind => panic!("unexpected error {:?} for {}", kind, input),
+            }
+        }
+        assert!(sequence("\"x\"1").is_ok());
+    }
+
+    fn error(input: &str) -> (Reason, Option<String>, Position) {
+        match parse(input).unwrap_err().kind() {
+            ErrorKind::InvalidJson(reason, token, position) => (*reason, token.clone(), *position),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        let (reason, token, position) = error("[1, 2 3]");
+        assert_eq!(reason, Reason::UnexpectedToken);
+        assert_eq!(token.as_deref(), Some("3"));
+        assert_eq!((position.offset, position.line, position.column), (6, 1, 7));
+
+        let (reason, token, position) = error("{\n  \"é\": [1,\n   }]}");
+        assert_eq!(reason, Reason::UnexpectedToken);
+        assert_eq!(token.as_deref(), Some("}"));
+        assert_eq!(
+            (position.offset, position.line, position.column),
+            (17, 3, 4)
+        );
+
+        for (input, offset) in [
+            (r#"{"a":}"#, 5),
+            (r#"[{"a": }]"#, 7),
+            (r#"{"a":1,"b":}"#, 11),
+        ] {
+            let (reason, token, position) = error(input);
+            assert_eq!(
+                (reason, token.as_deref()),
+                (Reason::UnexpectedToken, Some("}"))
+            );
+            assert_eq!(position.offset, offset, "{}", input);
+        }
+
+        let (reason, token, position) = error("[\"abc");
+        assert_eq!((reason, token), (Reason::UnexpectedEnd, None));
+        assert_eq!((position.offset, position.line, position.column), (5, 1, 6));
+    }
 }
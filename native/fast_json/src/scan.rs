@@ -0,0 +1,260 @@
+//! Block-at-a-time scanning for the `Parser`'s hot loops. On x86_64 this uses
+//! AVX2 when the CPU has it and SSE2 otherwise; other targets get the
+//! byte-at-a-time fallback.
+
+fn is_whitespace(value: u8) -> bool {
+    matches!(value, b'\t' | b'\r' | b'\n' | b' ')
+}
+
+/// Whether a string scan has to stop at `value`: a quote, a backslash, a
+/// control character or the start of a multi-byte UTF-8 sequence.
+fn is_string_special(value: u8) -> bool {
+    matches!(value, b'"' | b'\\' | 0x00..=0x1f | 0x80..=0xff)
+}
+
+/// Returns the index of the first non-whitespace byte at or after `from`, or
+/// `bytes.len()` if there is none.
+pub fn skip_whitespace(bytes: &[u8], from: usize) -> usize {
+    // Most runs of whitespace are empty or a single space.
+    match bytes.get(from) {
+        Some(&value) if is_whitespace(value) => {}
+        _ => return from,
+    }
+    match bytes.get(from + 1) {
+        Some(&value) if is_whitespace(value) => {}
+        _ => return from + 1,
+    }
+    dispatch::skip_whitespace(bytes, from + 2)
+}
+
+/// Returns the index of the first byte at or after `from` that ends a run of
+/// plain ASCII inside a string, or `bytes.len()` if there is none.
+pub fn find_string_special(bytes: &[u8], from: usize) -> usize {
+    dispatch::find_string_special(bytes, from)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod dispatch {
+    use super::x86;
+    use std::sync::OnceLock;
+
+    /// Detected once, as the scans run for every token.
+    fn has_avx2() -> bool {
+        static AVX2: OnceLock<bool> = OnceLock::new();
+        *AVX2.get_or_init(|| is_x86_feature_detected!("avx2"))
+    }
+
+    pub fn skip_whitespace(bytes: &[u8], from: usize) -> usize {
+        if has_avx2() {
+            unsafe { x86::skip_whitespace_avx2(bytes, from) }
+        } else {
+            unsafe { x86::skip_whitespace_sse2(bytes, from) }
+        }
+    }
+
+    pub fn find_string_special(bytes: &[u8], from: usize) -> usize {
+        if has_avx2() {
+            unsafe { x86::find_string_special_avx2(bytes, from) }
+        } else {
+            unsafe { x86::find_string_special_sse2(bytes, from) }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+mod dispatch {
+    pub use super::portable::*;
+}
+
+mod portable {
+    use super::{is_string_special, is_whitespace};
+
+    pub fn skip_whitespace(bytes: &[u8], from: usize) -> usize {
+        let rest = &bytes[from.min(bytes.len())..];
+        match rest.iter().position(|&value| !is_whitespace(value)) {
+            Some(offset) => from + offset,
+            None => bytes.len(),
+        }
+    }
+
+    pub fn find_string_special(bytes: &[u8], from: usize) -> usize {
+        let rest = &bytes[from.min(bytes.len())..];
+        match rest.iter().position(|&value| is_string_special(value)) {
+            Some(offset) => from + offset,
+            None => bytes.len(),
+        }
+    }
+}
+
+/// Each function compares a whole block against the bytes of interest and
+/// turns the result into a bit mask, one bit per byte. The remainder that
+/// doesn't fill a block goes to the portable version.
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::portable;
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn skip_whitespace_sse2(bytes: &[u8], mut i: usize) -> usize {
+        let space = _mm_set1_epi8(b' ' as i8);
+        let tab = _mm_set1_epi8(b'\t' as i8);
+        let newline = _mm_set1_epi8(b'\n' as i8);
+        let carriage_return = _mm_set1_epi8(b'\r' as i8);
+
+        while i + 16 <= bytes.len() {
+            let block = _mm_loadu_si128(bytes.as_ptr().add(i) as *const __m128i);
+            let whitespace = _mm_or_si128(
+                _mm_or_si128(_mm_cmpeq_epi8(block, space), _mm_cmpeq_epi8(block, tab)),
+                _mm_or_si128(
+                    _mm_cmpeq_epi8(block, newline),
+                    _mm_cmpeq_epi8(block, carriage_return),
+                ),
+            );
+            let mask = !(_mm_movemask_epi8(whitespace) as u32) & 0xFFFF;
+            if mask != 0 {
+                return i + mask.trailing_zeros() as usize;
+            }
+            i += 16;
+        }
+        portable::skip_whitespace(bytes, i)
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn find_string_special_sse2(bytes: &[u8], mut i: usize) -> usize {
+        let quote = _mm_set1_epi8(b'"' as i8);
+        let backslash = _mm_set1_epi8(b'\\' as i8);
+        let space = _mm_set1_epi8(b' ' as i8);
+
+        while i + 16 <= bytes.len() {
+            let block = _mm_loadu_si128(bytes.as_ptr().add(i) as *const __m128i);
+            // As signed bytes, 0x80..=0xFF are negative, so this one compare
+            // catches both control characters and non-ASCII.
+            let special = _mm_or_si128(
+                _mm_or_si128(
+                    _mm_cmpeq_epi8(block, quote),
+                    _mm_cmpeq_epi8(block, backslash),
+                ),
+                _mm_cmplt_epi8(block, space),
+            );
+            let mask = _mm_movemask_epi8(special) as u32;
+            if mask != 0 {
+                return i + mask.trailing_zeros() as usize;
+            }
+            i += 16;
+        }
+        portable::find_string_special(bytes, i)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn skip_whitespace_avx2(bytes: &[u8], mut i: usize) -> usize {
+        let space = _mm256_set1_epi8(b' ' as i8);
+        let tab = _mm256_set1_epi8(b'\t' as i8);
+        let newline = _mm256_set1_epi8(b'\n' as i8);
+        let carriage_return = _mm256_set1_epi8(b'\r' as i8);
+
+        while i + 32 <= bytes.len() {
+            let block = _mm256_loadu_si256(bytes.as_ptr().add(i) as *const __m256i);
+            let whitespace = _mm256_or_si256(
+                _mm256_or_si256(
+                    _mm256_cmpeq_epi8(block, space),
+                    _mm256_cmpeq_epi8(block, tab),
+                ),
+                _mm256_or_si256(
+                    _mm256_cmpeq_epi8(block, newline),
+                    _mm256_cmpeq_epi8(block, carriage_return),
+                ),
+            );
+            let mask = !(_mm256_movemask_epi8(whitespace) as u32);
+            if mask != 0 {
+                return i + mask.trailing_zeros() as usize;
+            }
+            i += 32;
+        }
+        skip_whitespace_sse2(bytes, i)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn find_string_special_avx2(bytes: &[u8], mut i: usize) -> usize {
+        let quote = _mm256_set1_epi8(b'"' as i8);
+        let backslash = _mm256_set1_epi8(b'\\' as i8);
+        let space = _mm256_set1_epi8(b' ' as i8);
+
+        while i + 32 <= bytes.len() {
+            let block = _mm256_loadu_si256(bytes.as_ptr().add(i) as *const __m256i);
+            // See the SSE2 version; there is no signed less-than, so swap the
+            // operands of greater-than.
+            let special = _mm256_or_si256(
+                _mm256_or_si256(
+                    _mm256_cmpeq_epi8(block, quote),
+                    _mm256_cmpeq_epi8(block, backslash),
+                ),
+                _mm256_cmpgt_epi8(space, block),
+            );
+            let mask = _mm256_movemask_epi8(special) as u32;
+            if mask != 0 {
+                return i + mask.trailing_zeros() as usize;
+            }
+            i += 32;
+        }
+        find_string_special_sse2(bytes, i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inputs where the interesting byte lands on either side of every block
+    /// boundary, for each kind of byte that should stop the scan.
+    fn inputs(fill: u8, stops: &[u8]) -> Vec<(Vec<u8>, usize)> {
+        let mut inputs = vec![];
+        for len in 0..80 {
+            for &stop in stops {
+                for position in 0..=len {
+                    let mut bytes = vec![fill; len];
+                    if position < len {
+                        bytes[position] = stop;
+                    }
+                    for from in [0, 1, 3] {
+                        if from <= len {
+                            inputs.push((bytes.clone(), from));
+                        }
+                    }
+                }
+            }
+        }
+        inputs
+    }
+
+    #[test]
+    fn skips_whitespace() {
+        for fill in [b' ', b'\t', b'\n', b'\r'] {
+            for (bytes, from) in inputs(fill, b"x\"{0\x0b\x80") {
+                let expected = portable::skip_whitespace(&bytes, from);
+                assert_eq!(skip_whitespace(&bytes, from), expected, "{:?}", bytes);
+                #[cfg(target_arch = "x86_64")]
+                unsafe {
+                    assert_eq!(x86::skip_whitespace_sse2(&bytes, from), expected);
+                    if is_x86_feature_detected!("avx2") {
+                        assert_eq!(x86::skip_whitespace_avx2(&bytes, from), expected);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn finds_string_specials() {
+        for (bytes, from) in inputs(b'a', b"\"\\\x00\x1f\x7f\x80\xff ~") {
+            let expected = portable::find_string_special(&bytes, from);
+            assert_eq!(find_string_special(&bytes, from), expected, "{:?}", bytes);
+            #[cfg(target_arch = "x86_64")]
+            unsafe {
+                assert_eq!(x86::find_string_special_sse2(&bytes, from), expected);
+                if is_x86_feature_detected!("avx2") {
+                    assert_eq!(x86::find_string_special_avx2(&bytes, from), expected);
+                }
+            }
+        }
+    }
+}
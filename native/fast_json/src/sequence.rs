@@ -0,0 +1,72 @@
+use rustler::{Binary, Encoder, Env, Error, Term};
+
+use crate::atoms;
+use crate::errors;
+use crate::options::DecodeOptions;
+use crate::parser::{Parser, ParserState};
+use crate::sink::TermSink;
+use crate::util::{error, ok};
+
+/// A sequence of documents, either concatenated with or without whitespace
+/// between them, or each one started by an RFC 7464 record separator. Both
+/// forms may be mixed.
+#[rustler::nif(schedule = "DirtyCpu")]
+pub fn decode_sequence_dirty<'a>(
+    env: Env<'a>,
+    data: Binary<'a>,
+    options: DecodeOptions,
+) -> Result<Term<'a>, Error> {
+    let mut documents = vec![];
+    let mut offset = 0;
+
+    loop {
+        match parse_next(env, &data, offset, options) {
+            Ok(Some((document, next))) => {
+                documents.push(document);
+                offset = next;
+            }
+            Ok(None) => return ok(env, documents.encode(env)),
+            Err(err) => return error(env, err),
+        }
+    }
+}
+
+/// Returns `{:ok, document, offset}` for the document starting at or after
+/// `offset`, where the returned offset is just past it, or `:done` if only
+/// separators are left.
+#[rustler::nif]
+pub fn decode_next<'a>(
+    env: Env<'a>,
+    data: Binary<'a>,
+    offset: usize,
+    options: DecodeOptions,
+) -> Result<Term<'a>, Error> {
+    if offset > data.len() {
+        return Err(Error::BadArg);
+    }
+
+    match parse_next(env, &data, offset, options) {
+        Ok(Some((document, next))) => Ok((atoms::ok(), document, next).encode(env)),
+        Ok(None) => Ok(atoms::done().encode(env)),
+        Err(err) => error(env, err),
+    }
+}
+
+fn parse_next<'a>(
+    env: Env<'a>,
+    data: &Binary<'a>,
+    offset: usize,
+    options: DecodeOptions,
+) -> errors::Result<Option<(Term<'a>, usize)>> {
+    let state = ParserState::at_offset(offset);
+    let mut parser = Parser::resume(data.as_slice(), state, options.parser).sequence(true);
+    if !parser.next_document() {
+        return Ok(None);
+    }
+
+    // `Binary` isn't `Clone`, and a sub-binary of the whole is cheap.
+    let source = data.make_subbinary(0, data.len()).unwrap();
+    let mut sink = TermSink::new(env, vec![], Some(source), options.sink);
+    while !parser.parse(&mut sink)? {}
+    Ok(Some((sink.pop(), parser.offset())))
+}
@@ -1,35 +1,196 @@
 use crate::atoms;
+use crate::parser::KeyAt;
+use crate::util::{integral_digits, make_atom, make_bignum, make_decimal};
 use rustler::types::map::map_new;
-use rustler::{Encoder, Env, Term};
+use rustler::types::tuple::make_tuple;
+use rustler::{Atom, Binary, Encoder, Env, OwnedBinary, Term};
+use std::fmt;
 
+pub mod recorder;
 pub mod value_sink;
-pub use self::value_sink::ValueSink;
+pub use self::recorder::Recorder;
+pub use self::value_sink::{NonFinite, SinkError, ValueSink};
+
+/// How object keys are turned into terms.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Keys {
+    /// Binaries, as they appear in the document.
+    #[default]
+    Strings,
+    /// Atoms, creating them as needed. Only for trusted input, since atoms
+    /// are never garbage collected.
+    Atoms,
+    /// Atoms, failing on keys that don't name an atom that already exists.
+    ExistingAtoms,
+}
+
+/// What to do when an object has the same key more than once.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Duplicates {
+    /// Keep the last value, as most JSON decoders do.
+    #[default]
+    Last,
+    /// Keep the first value and ignore the rest.
+    First,
+    /// Fail with an error naming the key. Parsers disagreeing on which value
+    /// wins is a known way to smuggle data past validation.
+    Reject,
+}
+
+/// How objects are turned into terms.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Objects {
+    /// Maps, which don't keep the order of the members.
+    #[default]
+    Maps,
+    /// Lists of `{key, value}` tuples, in the order of the document.
+    Proplists,
+    /// Proplists wrapped in a 1-tuple, as jiffy decodes objects.
+    Ejson,
+}
+
+/// How numbers with a fraction or an exponent are turned into terms.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Floats {
+    /// Floats, rounding to the nearest one.
+    #[default]
+    Native,
+    /// `%Decimal{}` structs holding exactly the digits in the document.
+    Decimals,
+}
+
+/// The atom JSON `null` decodes to.
+#[derive(Clone, Copy, PartialEq)]
+pub struct NullTerm(pub Atom);
+
+impl Default for NullTerm {
+    fn default() -> NullTerm {
+        NullTerm(atoms::nil())
+    }
+}
+
+/// The atoms standing for the non-finite numbers, both when decoding and
+/// when encoding.
+#[derive(Clone, Copy, PartialEq)]
+pub struct NonFiniteAtoms {
+    pub nan: Atom,
+    pub infinity: Atom,
+    pub negative_infinity: Atom,
+}
+
+impl Default for NonFiniteAtoms {
+    fn default() -> NonFiniteAtoms {
+        NonFiniteAtoms {
+            nan: atoms::nan(),
+            infinity: atoms::infinity(),
+            negative_infinity: atoms::negative_infinity(),
+        }
+    }
+}
+
+/// Implements `Debug` for types holding atoms without showing them, since
+/// `Atom` is not `Debug`: printing one needs an `Env`.
+macro_rules! debug_without_atoms {
+    ($($name:ident),*) => {
+        $(impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_struct(stringify!($name)).finish_non_exhaustive()
+            }
+        })*
+    };
+}
+
+debug_without_atoms!(NullTerm, NonFiniteAtoms);
+
+impl NonFiniteAtoms {
+    pub fn atom(&self, value: NonFinite) -> Atom {
+        match value {
+            NonFinite::NaN => self.nan,
+            NonFinite::Infinity => self.infinity,
+            NonFinite::NegativeInfinity => self.negative_infinity,
+        }
+    }
+
+    /// The number `atom` stands for, if any.
+    pub fn number(&self, atom: Atom) -> Option<NonFinite> {
+        [
+            NonFinite::NaN,
+            NonFinite::Infinity,
+            NonFinite::NegativeInfinity,
+        ]
+        .iter()
+        .copied()
+        .find(|&value| self.atom(value) == atom)
+    }
+}
+
+/// Settings that change which terms the `TermSink` builds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SinkOptions {
+    pub keys: Keys,
+    pub duplicates: Duplicates,
+    pub objects: Objects,
+    pub floats: Floats,
+    /// Decode floats with an integral value, such as `1.0` or `1e2`, to
+    /// integers.
+    pub integral_floats: bool,
+    /// Copy every string instead of returning sub-binaries of the source,
+    /// which would keep the whole source alive for as long as any of them.
+    pub copy_strings: bool,
+    /// Set along with `ParserOptions::non_finite`.
+    pub non_finite: Option<NonFiniteAtoms>,
+    pub null_term: NullTerm,
+}
 
 pub struct TermSink<'a> {
     env: Env<'a>,
     stack: Vec<Term<'a>>,
+    /// The binary being parsed, for making sub-binaries of its strings.
+    source: Option<Binary<'a>>,
+    options: SinkOptions,
 }
 
 impl<'a> TermSink<'a> {
-    pub fn new(env: Env<'a>, stack: Vec<Term<'a>>) -> TermSink<'a> {
+    pub fn new(
+        env: Env<'a>,
+        stack: Vec<Term<'a>>,
+        source: Option<Binary<'a>>,
+        options: SinkOptions,
+    ) -> TermSink<'a> {
         TermSink {
-            env: env,
-            stack: stack,
+            env,
+            stack,
+            source,
+            options,
         }
     }
 
-    pub fn to_stack(self) -> Vec<Term<'a>> {
+    pub fn into_stack(self) -> Vec<Term<'a>> {
         self.stack
     }
 
     pub fn pop(&mut self) -> Term<'a> {
         self.stack.pop().unwrap()
     }
+
+    /// Copies `bytes` into a new binary. Strings are not guaranteed to be
+    /// valid UTF-8 (see `LoneSurrogates::Wtf8`), so this skips `String`.
+    fn make_binary(&self, bytes: &[u8]) -> Term<'a> {
+        let mut binary = OwnedBinary::new(bytes.len()).unwrap();
+        binary.as_mut_slice().copy_from_slice(bytes);
+        binary.release(self.env).to_term(self.env)
+    }
 }
 
 impl<'a> ValueSink for TermSink<'a> {
+    /// Unless objects are maps, an object is kept on the stack as a map of
+    /// its members under a list of its keys in reverse order, so that
+    /// duplicates are found quickly and the order is known at the end.
     fn push_map(&mut self) {
         self.stack.push(map_new(self.env));
+        if self.options.objects != Objects::Maps {
+            self.stack.push(Term::list_new_empty(self.env));
+        }
     }
 
     fn push_array(&mut self) {
@@ -37,16 +198,76 @@ impl<'a> ValueSink for TermSink<'a> {
         self.stack.push(vector.encode(self.env));
     }
 
-    fn push_string(&mut self, string: String) {
-        self.stack.push(string.encode(self.env));
+    fn push_string(&mut self, string: &[u8]) {
+        let term = self.make_binary(string);
+        self.stack.push(term);
+    }
+
+    fn push_source_string(&mut self, string: &[u8], offset: usize) {
+        match &self.source {
+            Some(source) if !self.options.copy_strings => {
+                let term = source.make_subbinary(offset, string.len()).unwrap();
+                self.stack.push(term.to_term(self.env));
+            }
+            _ => self.push_string(string),
+        }
     }
 
     fn push_integer(&mut self, integer: i64) {
         self.stack.push(integer.encode(self.env));
     }
 
-    fn push_float(&mut self, float: f64) {
-        self.stack.push(float.encode(self.env));
+    fn push_big_integer(&mut self, digits: &str) {
+        let term = match digits.parse::<u64>() {
+            Ok(integer) => integer.encode(self.env),
+            Err(_) => make_bignum(self.env, digits),
+        };
+        self.stack.push(term);
+    }
+
+    fn push_float(&mut self, text: &str, _at: KeyAt) -> Result<(), SinkError> {
+        if self.options.integral_floats {
+            if let Some(digits) = integral_digits(text) {
+                match digits.parse::<i64>() {
+                    Ok(integer) => self.push_integer(integer),
+                    Err(_) => self.push_big_integer(&digits),
+                }
+                return Ok(());
+            }
+        }
+
+        let term = match self.options.floats {
+            Floats::Native => {
+                // Rust parses anything the grammar allows, even when lenient,
+                // but rounds numbers past the `f64` range to infinity.
+                let float = text.parse::<f64>().unwrap();
+                if float.is_finite() {
+                    float.encode(self.env)
+                } else {
+                    let atoms = self.options.non_finite.ok_or(SinkError::FloatOverflow)?;
+                    let value = if float > 0.0 {
+                        NonFinite::Infinity
+                    } else {
+                        NonFinite::NegativeInfinity
+                    };
+                    atoms.atom(value).to_term(self.env)
+                }
+            }
+            Floats::Decimals => make_decimal(self.env, text),
+        };
+        self.stack.push(term);
+        Ok(())
+    }
+
+    fn push_non_finite(&mut self, value: NonFinite) {
+        let atoms = self.options.non_finite.unwrap_or_default();
+        self.stack.push(atoms.atom(value).to_term(self.env));
+    }
+
+    fn push_raw_number(&mut self, text: &str) {
+        let text = self.make_binary(text.as_bytes());
+        self.stack
+            .push((atoms::json_number(), text).encode(self.env));
     }
 
     fn push_bool(&mut self, boolean: bool) {
@@ -54,11 +275,26 @@ impl<'a> ValueSink for TermSink<'a> {
     }
 
     fn push_nil(&mut self) {
-        self.stack.push(atoms::nil().to_term(self.env));
+        self.stack.push(self.options.null_term.0.to_term(self.env));
     }
 
     fn finalize_map(&mut self) {
-        //
+        if self.options.objects == Objects::Maps {
+            return;
+        }
+
+        let keys = self.pop().list_reverse().ok().unwrap();
+        let map = self.pop();
+        let members: Vec<Term<'a>> = keys
+            .into_list_iterator()
+            .unwrap()
+            .map(|key| (key, map.map_get(key).unwrap()).encode(self.env))
+            .collect();
+        let list = members.encode(self.env);
+        self.stack.push(match self.options.objects {
+            Objects::Ejson => make_tuple(self.env, &[list]),
+            _ => list,
+        });
     }
 
     fn finalize_array(&mut self) {
@@ -66,12 +302,34 @@ impl<'a> ValueSink for TermSink<'a> {
         self.stack.push(array.list_reverse().ok().unwrap());
     }
 
-    fn pop_insert_map(&mut self, key: String) {
+    fn pop_insert_map(&mut self, key: &[u8], _at: KeyAt) -> Result<(), SinkError> {
+        let key = match self.options.keys {
+            Keys::Strings => self.make_binary(key),
+            Keys::Atoms => make_atom(self.env, key, true).ok_or(SinkError::InvalidAtom)?,
+            Keys::ExistingAtoms => make_atom(self.env, key, false).ok_or(SinkError::UnknownAtom)?,
+        };
         let value = self.pop();
-        let map = self.pop();
+        let ordered = self.options.objects != Objects::Maps;
+        let mut keys = if ordered { Some(self.pop()) } else { None };
+        let mut map = self.pop();
 
-        self.stack
-            .push(map.map_put(key.encode(self.env), value).ok().unwrap());
+        let duplicate =
+            (ordered || self.options.duplicates != Duplicates::Last) && map.map_get(key).is_ok();
+        let result = match self.options.duplicates {
+            Duplicates::Reject if duplicate => Err(SinkError::DuplicateKey),
+            Duplicates::First if duplicate => Ok(()),
+            _ => {
+                map = map.map_put(key, value).ok().unwrap();
+                if !duplicate {
+                    keys = keys.map(|keys| keys.list_prepend(key));
+                }
+                Ok(())
+            }
+        };
+
+        self.stack.push(map);
+        self.stack.extend(keys);
+        result
     }
 
     fn pop_insert_array(&mut self) {
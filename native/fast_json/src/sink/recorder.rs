@@ -0,0 +1,178 @@
+use super::{NonFinite, SinkError, ValueSink};
+use crate::errors::{self, Position};
+use crate::parser::{sink_error, KeyAt};
+
+/// One call made on a `ValueSink`.
+#[derive(Debug)]
+enum Event {
+    Map,
+    Array,
+    String(Vec<u8>),
+    Integer(i64),
+    BigInteger(String),
+    /// Where the number is is kept in `Recorder::places` too.
+    Float(String),
+    NonFinite(NonFinite),
+    RawNumber(String),
+    Bool(bool),
+    Nil,
+    FinalizeMap,
+    FinalizeArray,
+    /// Where the key is is kept in `Recorder::places`.
+    InsertMap(Vec<u8>),
+    InsertArray,
+}
+
+/// Records the values parsed from a stream, which arrive over several calls,
+/// so that terms can be built from them in one go once the stream ends.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    events: Vec<Event>,
+    /// Where each inserted key and each float is, in order. Those from
+    /// before the last `locate` are all positions.
+    places: Vec<KeyAt>,
+    located: usize,
+}
+
+impl Recorder {
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Forgets the events after the first `len`, which must all have been
+    /// recorded since the last `locate`.
+    pub fn truncate(&mut self, len: usize) {
+        let dropped = self.events[len..]
+            .iter()
+            .filter(|event| matches!(event, Event::InsertMap(_) | Event::Float(_)))
+            .count();
+        self.events.truncate(len);
+        self.places.truncate(self.places.len() - dropped);
+    }
+
+    /// Turns the key and float offsets into positions while the chunk they
+    /// are relative to, which starts at `origin`, is still around.
+    pub fn locate(&mut self, source: &[u8], origin: Position) {
+        // A key is recorded once its value is done, so nested keys come first
+        // and the offsets are not in order.
+        let mut offsets: Vec<(usize, usize)> = self.places[self.located..]
+            .iter()
+            .enumerate()
+            .filter_map(|(index, at)| match at {
+                KeyAt::Offset(offset) => Some((*offset, self.located + index)),
+                KeyAt::Position(_) => None,
+            })
+            .collect();
+        offsets.sort_unstable();
+
+        let (mut position, mut offset) = (origin, 0);
+        for (next, index) in offsets {
+            position = position.advance(&source[offset..next]);
+            offset = next;
+            self.places[index] = KeyAt::Position(position);
+        }
+        self.located = self.places.len();
+    }
+
+    /// Makes the recorded calls on `sink`, failing on the first key or float
+    /// it refuses.
+    pub fn replay<S: ValueSink>(&self, sink: &mut S) -> errors::Result<()> {
+        let mut places = self.places.iter();
+        let position = |at: KeyAt| match at {
+            KeyAt::Position(position) => position,
+            KeyAt::Offset(_) => unreachable!("replayed before locate"),
+        };
+
+        for event in &self.events {
+            match event {
+                Event::Map => sink.push_map(),
+                Event::Array => sink.push_array(),
+                Event::String(string) => sink.push_string(string),
+                Event::Integer(integer) => sink.push_integer(*integer),
+                Event::BigInteger(digits) => sink.push_big_integer(digits),
+                Event::Float(text) => {
+                    let at = *places.next().unwrap();
+                    if let Err(err) = sink.push_float(text, at) {
+                        return Err(sink_error(err, text.as_bytes(), position(at)));
+                    }
+                }
+                Event::NonFinite(value) => sink.push_non_finite(*value),
+                Event::RawNumber(text) => sink.push_raw_number(text),
+                Event::Bool(boolean) => sink.push_bool(*boolean),
+                Event::Nil => sink.push_nil(),
+                Event::FinalizeMap => sink.finalize_map(),
+                Event::FinalizeArray => sink.finalize_array(),
+                Event::InsertMap(key) => {
+                    let at = *places.next().unwrap();
+                    if let Err(err) = sink.pop_insert_map(key, at) {
+                        return Err(sink_error(err, key, position(at)));
+                    }
+                }
+                Event::InsertArray => sink.pop_insert_array(),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ValueSink for Recorder {
+    fn push_map(&mut self) {
+        self.events.push(Event::Map);
+    }
+
+    fn push_array(&mut self) {
+        self.events.push(Event::Array);
+    }
+
+    fn push_string(&mut self, string: &[u8]) {
+        self.events.push(Event::String(string.to_vec()));
+    }
+
+    fn push_integer(&mut self, integer: i64) {
+        self.events.push(Event::Integer(integer));
+    }
+
+    fn push_big_integer(&mut self, digits: &str) {
+        self.events.push(Event::BigInteger(digits.to_string()));
+    }
+
+    fn push_float(&mut self, text: &str, at: KeyAt) -> Result<(), SinkError> {
+        self.events.push(Event::Float(text.to_string()));
+        self.places.push(at);
+        Ok(())
+    }
+
+    fn push_non_finite(&mut self, value: NonFinite) {
+        self.events.push(Event::NonFinite(value));
+    }
+
+    fn push_raw_number(&mut self, text: &str) {
+        self.events.push(Event::RawNumber(text.to_string()));
+    }
+
+    fn push_bool(&mut self, boolean: bool) {
+        self.events.push(Event::Bool(boolean));
+    }
+
+    fn push_nil(&mut self) {
+        self.events.push(Event::Nil);
+    }
+
+    fn finalize_map(&mut self) {
+        self.events.push(Event::FinalizeMap);
+    }
+
+    fn finalize_array(&mut self) {
+        self.events.push(Event::FinalizeArray);
+    }
+
+    fn pop_insert_map(&mut self, key: &[u8], at: KeyAt) -> Result<(), SinkError> {
+        self.events.push(Event::InsertMap(key.to_vec()));
+        self.places.push(at);
+        Ok(())
+    }
+
+    fn pop_insert_array(&mut self) {
+        self.events.push(Event::InsertArray);
+    }
+}
@@ -1,13 +1,63 @@
+use crate::parser::KeyAt;
+
+/// Why a sink refused an object key or a float. The parser reports it along
+/// with the key or number and its position.
+#[derive(Debug)]
+pub enum SinkError {
+    /// The key does not name an atom that already exists.
+    UnknownAtom,
+    /// The key cannot be represented as an atom at all.
+    InvalidAtom,
+    /// The object already has the key and `Duplicates::Reject` is set.
+    DuplicateKey,
+    /// The float is out of the `f64` range and `non_finite` is not set.
+    FloatOverflow,
+}
+
+/// A number JSON has no syntax for, accepted when `non_finite` is set.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NonFinite {
+    NaN,
+    Infinity,
+    NegativeInfinity,
+}
+
+impl NonFinite {
+    /// The token for the number, as JavaScript and Python write it.
+    pub fn name(self) -> &'static str {
+        match self {
+            NonFinite::NaN => "NaN",
+            NonFinite::Infinity => "Infinity",
+            NonFinite::NegativeInfinity => "-Infinity",
+        }
+    }
+}
+
 pub trait ValueSink {
     fn push_map(&mut self);
     fn push_array(&mut self);
-    fn push_string(&mut self, string: String);
+    fn push_string(&mut self, string: &[u8]);
+    /// Pushes a string that appears verbatim in the source, starting at
+    /// `offset`, so a sink that holds the source can reference it in place.
+    fn push_source_string(&mut self, string: &[u8], _offset: usize) {
+        self.push_string(string);
+    }
     fn push_integer(&mut self, integer: i64);
-    fn push_float(&mut self, float: f64);
+    /// Pushes an integer outside the `i64` range, given as an optionally
+    /// `-`-prefixed run of decimal digits.
+    fn push_big_integer(&mut self, digits: &str);
+    /// Pushes a number with a fraction or an exponent, given as its text. It
+    /// follows the JSON grammar, except that the integer part may be missing
+    /// when lenient. `at` is where the number starts.
+    fn push_float(&mut self, text: &str, at: KeyAt) -> Result<(), SinkError>;
+    fn push_non_finite(&mut self, value: NonFinite);
+    /// Pushes a number exactly as it is written, when `raw_numbers` is set.
+    fn push_raw_number(&mut self, text: &str);
     fn push_bool(&mut self, boolean: bool);
     fn push_nil(&mut self);
     fn finalize_map(&mut self);
     fn finalize_array(&mut self);
-    fn pop_insert_map(&mut self, key: String);
+    /// `at` is where the key's opening quote is.
+    fn pop_insert_map(&mut self, key: &[u8], at: KeyAt) -> Result<(), SinkError>;
     fn pop_insert_array(&mut self);
 }
@@ -0,0 +1,256 @@
+use std::sync::Mutex;
+
+use rustler::{Binary, Encoder, Env, Error, ResourceArc, Term};
+
+use crate::atoms;
+use crate::errors;
+use crate::options::DecodeOptions;
+use crate::parser::{Parser, ParserOptions, ParserState};
+use crate::sink::{Recorder, TermSink};
+use crate::util::{error, ok};
+
+pub struct StreamResource {
+    /// `None` once the stream has been finished or has failed.
+    stream: Mutex<Option<Stream>>,
+    options: DecodeOptions,
+}
+
+/// A document arriving in chunks. Between chunks, only the bytes from the
+/// start of the step the parser stopped in are kept.
+#[derive(Default)]
+struct Stream {
+    buffer: Vec<u8>,
+    state: ParserState,
+    recorder: Recorder,
+    /// Whether the value is complete, so that only whitespace may follow.
+    complete: bool,
+    /// How long the buffer has to grow before the step the parser stopped in
+    /// is tried again.
+    retry_len: usize,
+}
+
+impl Stream {
+    /// Parses as much of the buffer as it can. Unless `partial` is set, the
+    /// end of the buffer is the end of the document.
+    ///
+    /// A step that runs into the end of the buffer starts over once more has
+    /// arrived, rescanning what it already saw. So that a long string or
+    /// number fed in small chunks takes linear rather than quadratic time,
+    /// the step is only retried once the buffer has doubled, unless a limit
+    /// might have been exceeded in the meantime.
+    fn parse(&mut self, options: ParserOptions, partial: bool) -> errors::Result<()> {
+        let within_limits = self.state.origin().offset + self.buffer.len() <= options.max_bytes
+            && self.buffer.len() <= options.max_string_length.min(options.max_number_digits);
+        if partial && within_limits && self.buffer.len() < self.retry_len {
+            return Ok(());
+        }
+
+        let state = std::mem::take(&mut self.state);
+        let mut parser = Parser::resume(&self.buffer, state, options).partial(partial);
+
+        while !self.complete {
+            let step = if partial {
+                parser.parse_partial(&mut self.recorder)?
+            } else {
+                Some(parser.parse(&mut self.recorder)?)
+            };
+            match step {
+                Some(true) => self.complete = true,
+                Some(false) => continue,
+                None => break,
+            }
+        }
+        if self.complete {
+            parser.parse_trailing()?;
+        }
+
+        let mut state = parser.suspend();
+        self.recorder.locate(&self.buffer, state.origin());
+        let consumed = state.offset();
+        state.rebase(&self.buffer[..consumed]);
+        self.buffer.drain(..consumed);
+        self.state = state;
+        self.retry_len = 2 * self.buffer.len();
+        Ok(())
+    }
+}
+
+#[rustler::nif]
+pub fn decode_stream_init(options: DecodeOptions) -> ResourceArc<StreamResource> {
+    ResourceArc::new(StreamResource {
+        stream: Mutex::new(Some(Stream::default())),
+        options,
+    })
+}
+
+/// Parses as much of the buffered input as the chunk completes, which can
+/// take a while for a big chunk.
+#[rustler::nif(schedule = "DirtyCpu")]
+pub fn decode_stream_feed<'a>(
+    env: Env<'a>,
+    resource: ResourceArc<StreamResource>,
+    chunk: Binary,
+) -> Result<Term<'a>, Error> {
+    let mut guard = match resource.stream.try_lock() {
+        Err(_) => return Err(Error::BadArg),
+        Ok(guard) => guard,
+    };
+    let stream = guard.as_mut().ok_or(Error::BadArg)?;
+
+    stream.buffer.extend_from_slice(&chunk);
+    match stream.parse(resource.options.parser, true) {
+        Ok(()) => Ok((atoms::more(), resource.clone()).encode(env)),
+        Err(err) => {
+            *guard = None;
+            error(env, err)
+        }
+    }
+}
+
+/// Builds all the terms at once, which can take a while for a big document.
+#[rustler::nif(schedule = "DirtyCpu")]
+pub fn decode_stream_finish(
+    env: Env,
+    resource: ResourceArc<StreamResource>,
+) -> Result<Term, Error> {
+    let mut stream = match resource.stream.try_lock() {
+        Err(_) => return Err(Error::BadArg),
+        Ok(mut guard) => guard.take().ok_or(Error::BadArg)?,
+    };
+
+    if let Err(err) = stream.parse(resource.options.parser, false) {
+        return error(env, err);
+    }
+
+    let mut sink = TermSink::new(env, vec![], None, resource.options.sink);
+    match stream.recorder.replay(&mut sink) {
+        Ok(()) => ok(env, sink.pop()),
+        Err(err) => error(env, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{ErrorKind, Reason};
+    use crate::parser::tests::{self, TextSink};
+
+    fn parse_chunks(chunks: &[&[u8]]) -> errors::Result<String> {
+        parse_chunks_with(chunks, ParserOptions::default())
+    }
+
+    fn parse_chunks_with(chunks: &[&[u8]], options: ParserOptions) -> errors::Result<String> {
+        let mut stream = Stream::default();
+        for chunk in chunks {
+            stream.buffer.extend_from_slice(chunk);
+            stream.parse(options, true)?;
+        }
+        stream.parse(options, false)?;
+
+        let mut sink = TextSink::default();
+        stream.recorder.replay(&mut sink)?;
+        Ok(sink.stack.pop().unwrap().0)
+    }
+
+    fn check_splits(input: &str, options: ParserOptions, expected: &str) {
+        let bytes = input.as_bytes();
+
+        for i in 0..=bytes.len() {
+            for j in i..=bytes.len().min(i + 8) {
+                let chunks = [&bytes[..i], &bytes[i..j], &bytes[j..]];
+                assert_eq!(
+                    parse_chunks_with(&chunks, options).unwrap(),
+                    expected,
+                    "split at {} and {}",
+                    i,
+                    j
+                );
+            }
+        }
+        let chunks: Vec<&[u8]> = bytes.chunks(1).collect();
+        assert_eq!(parse_chunks_with(&chunks, options).unwrap(), expected);
+    }
+
+    #[test]
+    fn parses_across_any_split() {
+        let input = r#" {"a": [1, -2.5e3, "x\u00e9\ud83d\ude00y", "é😀"], "bc": {"d": [true, false, null, []]}, "e": 12345678901234567890} "#;
+        let expected = tests::parse(input).unwrap();
+        check_splits(input, ParserOptions::default(), &expected);
+        assert_eq!(parse_chunks(&[b"12", b"3"]).unwrap(), "123");
+
+        let lenient = ParserOptions {
+            lenient: true,
+            ..ParserOptions::default()
+        };
+        let input = "// c\n{key: ['a\\'b', 0x1f, +.5,], /* c */ k2: null} // c";
+        check_splits(input, lenient, r#"{"key":["a'b",31,0.5],"k2":null}"#);
+
+        let non_finite = ParserOptions {
+            non_finite: true,
+            ..ParserOptions::default()
+        };
+        let input = "[NaN,-Infinity,-1,Infinity]";
+        check_splits(input, non_finite, input);
+    }
+
+    #[test]
+    fn retries_long_tokens_a_logarithmic_number_of_times() {
+        let chunk = [b'x'; 1024];
+        let mut stream = Stream::default();
+        stream.buffer.extend_from_slice(b"[\"");
+        let mut retries = 0;
+        for _ in 0..1024 {
+            let retry_len = stream.retry_len;
+            stream.buffer.extend_from_slice(&chunk);
+            stream.parse(ParserOptions::default(), true).unwrap();
+            if stream.retry_len != retry_len {
+                retries += 1;
+            }
+        }
+        assert!(retries <= 12, "{} retries", retries);
+
+        stream.buffer.extend_from_slice(b"\", 1]");
+        stream.parse(ParserOptions::default(), true).unwrap();
+        stream.parse(ParserOptions::default(), false).unwrap();
+        let mut sink = TextSink::default();
+        stream.recorder.replay(&mut sink).unwrap();
+        let expected = format!("[{:?},1]", "x".repeat(1024 * 1024));
+        assert_eq!(sink.stack.pop().unwrap().0, expected);
+
+        // Limits are still checked on every chunk, so the string is too long
+        // as soon as the 11th chunk arrives.
+        let options = ParserOptions {
+            max_string_length: 100,
+            ..ParserOptions::default()
+        };
+        let mut stream = Stream::default();
+        stream.buffer.extend_from_slice(b"[\"");
+        let mut chunks = 0;
+        while stream.parse(options, true).is_ok() {
+            stream.buffer.extend_from_slice(&chunk[..10]);
+            chunks += 1;
+        }
+        assert_eq!(chunks, 11);
+    }
+
+    #[test]
+    fn reports_positions_in_the_whole_stream() {
+        let error = |chunks: &[&[u8]]| match parse_chunks(chunks).unwrap_err().kind() {
+            ErrorKind::InvalidJson(reason, _, position) => {
+                (*reason, position.offset, position.line, position.column)
+            }
+            kind => panic!("unexpected error {:?}", kind),
+        };
+
+        assert_eq!(
+            error(&[b"[1,\n", b"  2,", b" x]"]),
+            (Reason::UnexpectedToken, 9, 2, 6)
+        );
+        assert_eq!(error(&[b"[1, 2", b""]), (Reason::UnexpectedEnd, 5, 1, 6));
+        assert_eq!(
+            error(&[b"[\"\xC3", b"\xA9\xFF\"]"]),
+            (Reason::InvalidUtf8, 4, 1, 4)
+        );
+        assert_eq!(error(&[b"[]\n", b" x"]), (Reason::UnexpectedToken, 4, 2, 2));
+    }
+}
@@ -1,12 +1,371 @@
 use crate::atoms;
-use crate::errors;
-use rustler::{Encoder, Env, Error, Term};
+use crate::errors::{self, ErrorKind, Reason};
+use rustler::types::map::map_new;
+use rustler::{Atom, Encoder, Env, Error, Term};
+use std::fmt::Write;
 
 pub fn ok<'a>(env: Env<'a>, term: Term<'a>) -> Result<Term<'a>, Error> {
     Ok((atoms::ok(), term).encode(env))
 }
 
+/// Returns `{:error, details}`, where details is a map with the `:reason`,
+/// `:token`, `:position`, `:line` and `:column` of a decode error.
 pub fn error(env: Env, err: errors::Error) -> Result<Term, Error> {
-    let message = format!("{}", err).encode(env);
-    Ok((atoms::error(), message).encode(env))
+    Ok(error_term(env, err))
+}
+
+/// The `{:error, details}` term of `error`, for sending as a message.
+pub fn error_term(env: Env, err: errors::Error) -> Term {
+    let details = match err.kind() {
+        ErrorKind::InvalidJson(reason, token, position) => [
+            (atoms::reason(), reason_atom(*reason).encode(env)),
+            (atoms::token(), token.encode(env)),
+            (atoms::position(), position.offset.encode(env)),
+            (atoms::line(), position.line.encode(env)),
+            (atoms::column(), position.column.encode(env)),
+        ]
+        .iter()
+        .fold(map_new(env), |map, &(key, value)| {
+            // Putting distinct keys into a new map cannot fail.
+            map.map_put(key.encode(env), value).unwrap()
+        }),
+        _ => format!("{}", err).encode(env),
+    };
+    (atoms::error(), details).encode(env)
+}
+
+fn reason_atom(reason: Reason) -> Atom {
+    match reason {
+        Reason::UnexpectedEnd => atoms::unexpected_end(),
+        Reason::UnexpectedToken => atoms::unexpected_token(),
+        Reason::InvalidNumber => atoms::invalid_number(),
+        Reason::InvalidEscape => atoms::invalid_escape(),
+        Reason::InvalidUtf8 => atoms::invalid_utf8(),
+        Reason::LoneSurrogate => atoms::lone_surrogate(),
+        Reason::ControlCharacter => atoms::control_character(),
+        Reason::MaxDepthExceeded => atoms::max_depth_exceeded(),
+        Reason::InputTooLarge => atoms::input_too_large(),
+        Reason::StringTooLong => atoms::string_too_long(),
+        Reason::TooManyElements => atoms::too_many_elements(),
+        Reason::NumberTooLong => atoms::number_too_long(),
+        Reason::NonFiniteNumber => atoms::non_finite_number(),
+        Reason::UnknownAtom => atoms::unknown_atom(),
+        Reason::InvalidAtom => atoms::invalid_atom(),
+        Reason::DuplicateKey => atoms::duplicate_key(),
+    }
+}
+
+/// Builds an integer term of any size from an optionally `-`-prefixed run of
+/// decimal digits, by way of the external term format's bignum encoding.
+pub fn make_bignum<'a>(env: Env<'a>, digits: &str) -> Term<'a> {
+    let (sign, digits) = match digits.strip_prefix('-') {
+        Some(digits) => (1, digits),
+        None => (0, digits),
+    };
+
+    let magnitude = magnitude_bytes(digits);
+
+    let mut etf = vec![131];
+    if magnitude.len() <= u8::MAX as usize {
+        etf.push(110); // SMALL_BIG_EXT
+        etf.push(magnitude.len() as u8);
+    } else {
+        etf.push(111); // LARGE_BIG_EXT
+        etf.extend_from_slice(&(magnitude.len() as u32).to_be_bytes());
+    }
+    etf.push(sign);
+    etf.extend_from_slice(&magnitude);
+
+    env.binary_to_term(&etf).unwrap().0
+}
+
+/// The little-endian base 256 magnitude of a run of decimal digits, without
+/// high zero bytes.
+///
+/// The digits are taken 19 at a time, the most that fit in a `u64`, and
+/// folded into 64-bit limbs, so that a long number takes far fewer passes
+/// over the limbs than going digit by digit into bytes would.
+fn magnitude_bytes(digits: &str) -> Vec<u8> {
+    let mut limbs: Vec<u64> = vec![];
+    for chunk in digits.as_bytes().chunks(19) {
+        let scale = 10u64.pow(chunk.len() as u32);
+        let mut carry = chunk
+            .iter()
+            .fold(0, |value, digit| value * 10 + u64::from(digit - b'0'));
+        for limb in limbs.iter_mut() {
+            let value = u128::from(*limb) * u128::from(scale) + u128::from(carry);
+            *limb = value as u64;
+            carry = (value >> 64) as u64;
+        }
+        if carry > 0 {
+            limbs.push(carry);
+        }
+    }
+
+    let mut bytes: Vec<u8> = limbs.iter().flat_map(|limb| limb.to_le_bytes()).collect();
+    while bytes.last() == Some(&0) {
+        bytes.pop();
+    }
+    bytes
+}
+
+/// The digits of an integer term outside the `u64` and `i64` ranges,
+/// optionally `-`-prefixed, by way of the external term format's bignum
+/// encoding. `None` for any other term.
+pub fn bignum_digits(term: Term) -> Option<String> {
+    let etf = term.to_binary();
+    let (sign, magnitude) = match etf.as_slice() {
+        [131, 110, length, sign, rest @ ..] => (*sign, &rest[..usize::from(*length)]),
+        [131, 111, a, b, c, d, sign, rest @ ..] => {
+            let length = u32::from_be_bytes([*a, *b, *c, *d]) as usize;
+            (*sign, &rest[..length])
+        }
+        _ => return None,
+    };
+
+    let digits = magnitude_digits(magnitude);
+    Some(if sign == 1 {
+        format!("-{}", digits)
+    } else {
+        digits
+    })
+}
+
+/// The decimal digits of a little-endian base 256 magnitude, the inverse of
+/// `magnitude_bytes`. The limbs are divided by 10^19 at a time.
+pub fn magnitude_digits(bytes: &[u8]) -> String {
+    const CHUNK: u128 = 10_000_000_000_000_000_000;
+
+    // Most significant first, as long division goes.
+    let mut limbs: Vec<u64> = bytes
+        .chunks(8)
+        .rev()
+        .map(|chunk| {
+            let mut limb = [0; 8];
+            limb[..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(limb)
+        })
+        .collect();
+
+    // Least significant first.
+    let mut chunks: Vec<u64> = vec![];
+    loop {
+        let zeros = limbs.iter().take_while(|&&limb| limb == 0).count();
+        limbs.drain(..zeros);
+        if limbs.is_empty() {
+            break;
+        }
+        let mut remainder = 0u128;
+        for limb in limbs.iter_mut() {
+            let value = remainder << 64 | u128::from(*limb);
+            *limb = (value / CHUNK) as u64;
+            remainder = value % CHUNK;
+        }
+        chunks.push(remainder as u64);
+    }
+
+    let mut digits = chunks.pop().unwrap_or(0).to_string();
+    for chunk in chunks.iter().rev() {
+        write!(digits, "{:019}", chunk).unwrap();
+    }
+    digits
+}
+
+/// Builds a `%Decimal{}` struct holding exactly the number written as `text`,
+/// a JSON number whose integer part may be missing.
+pub fn make_decimal<'a>(env: Env<'a>, text: &str) -> Term<'a> {
+    let (sign, digits, exp) = decimal_parts(text);
+    let coef = match digits.parse::<i64>() {
+        Ok(coef) => coef.encode(env),
+        Err(_) => make_bignum(env, &digits),
+    };
+
+    map_new(env)
+        .map_put(
+            atoms::__struct__().encode(env),
+            atoms::decimal().encode(env),
+        )
+        .and_then(|map| map.map_put(atoms::sign().encode(env), sign.encode(env)))
+        .and_then(|map| map.map_put(atoms::coef().encode(env), coef))
+        .and_then(|map| map.map_put(atoms::exp().encode(env), exp.encode(env)))
+        .unwrap()
+}
+
+/// The most digits an integer decoded from a float-like number may have,
+/// being about as large as a float can be.
+const MAX_INTEGRAL_DIGITS: i64 = 309;
+
+/// Returns the optionally `-`-prefixed digits of the integer a JSON number
+/// such as `1.0` or `1e2` is equal to, or `None` if it has a fractional part
+/// or is too large to be a float.
+pub fn integral_digits(text: &str) -> Option<String> {
+    let (sign, digits, exp) = decimal_parts(text);
+    let significant = digits.trim_end_matches('0');
+    if significant.is_empty() {
+        return Some("0".to_string());
+    }
+
+    let zeros = exp.saturating_add((digits.len() - significant.len()) as i64);
+    if zeros < 0 || zeros > MAX_INTEGRAL_DIGITS - significant.len() as i64 {
+        return None;
+    }
+    let mut integer = String::new();
+    if sign < 0 {
+        integer.push('-');
+    }
+    integer.push_str(significant);
+    integer.push_str(&"0".repeat(zeros as usize));
+    Some(integer)
+}
+
+/// Splits a JSON number into the sign, coefficient digits and exponent of a
+/// `Decimal`, keeping trailing zeros as `Decimal.new/1` does.
+fn decimal_parts(text: &str) -> (i8, String, i64) {
+    let (sign, unsigned) = match text.strip_prefix('-') {
+        Some(unsigned) => (-1, unsigned),
+        None => (1, text),
+    };
+    let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+        Some(e) => (&unsigned[..e], &unsigned[e + 1..]),
+        None => (unsigned, "0"),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+
+    let mut digits: String = integer
+        .chars()
+        .chain(fraction.chars())
+        .skip_while(|&c| c == '0')
+        .collect();
+    if digits.is_empty() {
+        digits.push('0');
+    }
+    // Exponents beyond `i64` are clamped, being far outside any precision a
+    // `Decimal` context can work with anyway.
+    let exponent = exponent
+        .parse::<i64>()
+        .unwrap_or(if exponent.starts_with('-') {
+            i64::MIN
+        } else {
+            i64::MAX
+        });
+    (sign, digits, exponent.saturating_sub(fraction.len() as i64))
+}
+
+/// Builds an atom named by the UTF-8 `name`, by way of the external term
+/// format. Unless `create` is set, only atoms that already exist are
+/// returned, so untrusted input can't fill up the atom table.
+pub fn make_atom<'a>(env: Env<'a>, name: &[u8], create: bool) -> Option<Term<'a>> {
+    match std::str::from_utf8(name) {
+        Ok(name) if name.chars().count() <= 255 => (),
+        _ => return None,
+    }
+
+    let mut etf = vec![131];
+    if name.len() <= u8::MAX as usize {
+        etf.push(119); // SMALL_ATOM_UTF8_EXT
+        etf.push(name.len() as u8);
+    } else {
+        etf.push(118); // ATOM_UTF8_EXT
+        etf.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    }
+    etf.extend_from_slice(name);
+
+    let decoded = if create {
+        // The name was checked above, so this is a well-formed atom.
+        unsafe { env.binary_to_term_trusted(&etf) }
+    } else {
+        env.binary_to_term(&etf)
+    };
+    decoded.map(|(term, _)| term)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_digits_to_magnitudes() {
+        assert_eq!(magnitude_bytes("0"), Vec::<u8>::new());
+        assert_eq!(magnitude_bytes("255"), vec![255]);
+        assert_eq!(magnitude_bytes("256"), vec![0, 1]);
+        assert_eq!(
+            magnitude_bytes("18446744073709551616"),
+            vec![0, 0, 0, 0, 0, 0, 0, 0, 1]
+        );
+        // 2^128, spanning three chunks of digits.
+        let mut expected = vec![0; 16];
+        expected.push(1);
+        assert_eq!(
+            magnitude_bytes("340282366920938463463374607431768211456"),
+            expected
+        );
+
+        // 10^n has n trailing zero bits, so 10^100000 ends 12500 zero bytes
+        // into a magnitude of 41525 bytes.
+        let digits = format!("1{}", "0".repeat(100_000));
+        let magnitude = magnitude_bytes(&digits);
+        assert_eq!(magnitude.len(), 41_525);
+        assert!(magnitude[..12_500].iter().all(|&byte| byte == 0));
+        assert_ne!(magnitude[12_500], 0);
+    }
+
+    #[test]
+    fn converts_magnitudes_to_digits() {
+        assert_eq!(magnitude_digits(&[]), "0");
+        assert_eq!(magnitude_digits(&[0, 1, 0]), "256");
+        for digits in [
+            "18446744073709551616",
+            "10000000000000000000000000000000000000",
+            "340282366920938463463374607431768211456",
+            "123456789012345678901234567890123456789012345678901234567890",
+        ] {
+            assert_eq!(magnitude_digits(&magnitude_bytes(digits)), digits);
+        }
+
+        let digits = format!("9{}1", "0".repeat(10_000));
+        assert_eq!(magnitude_digits(&magnitude_bytes(&digits)), digits);
+    }
+
+    #[test]
+    fn splits_decimals_exactly() {
+        let expected = |sign, digits: &str, exp| (sign, digits.to_string(), exp);
+
+        assert_eq!(
+            decimal_parts("19.990000000000001"),
+            expected(1, "19990000000000001", -15)
+        );
+        assert_eq!(decimal_parts("-1.50"), expected(-1, "150", -2));
+        assert_eq!(decimal_parts("0.01"), expected(1, "1", -2));
+        assert_eq!(decimal_parts("-0.0"), expected(-1, "0", -1));
+        assert_eq!(decimal_parts("1e3"), expected(1, "1", 3));
+        assert_eq!(decimal_parts("12.5E-3"), expected(1, "125", -4));
+        assert_eq!(decimal_parts("1.5e+2"), expected(1, "15", 1));
+        assert_eq!(decimal_parts(".5"), expected(1, "5", -1));
+        assert_eq!(
+            decimal_parts("123456789012345678901234567890.1"),
+            expected(1, "1234567890123456789012345678901", -1)
+        );
+        assert_eq!(
+            decimal_parts("1e-99999999999999999999"),
+            expected(1, "1", i64::MIN)
+        );
+    }
+
+    #[test]
+    fn finds_integral_numbers() {
+        let integral = |text| integral_digits(text);
+
+        assert_eq!(integral("1.0"), Some("1".to_string()));
+        assert_eq!(integral("1e2"), Some("100".to_string()));
+        assert_eq!(integral("-12.50e1"), Some("-125".to_string()));
+        assert_eq!(integral("1500E-2"), Some("15".to_string()));
+        assert_eq!(integral("-0.0"), Some("0".to_string()));
+        assert_eq!(integral("0e-99999999999999999999"), Some("0".to_string()));
+        assert_eq!(integral("1.5"), None);
+        assert_eq!(integral("1e-2"), None);
+        assert_eq!(integral(".5"), None);
+        assert_eq!(integral("1e308").map(|digits| digits.len()), Some(309));
+        assert_eq!(integral("1e309"), None);
+        assert_eq!(integral("1e99999999999999999999"), None);
+    }
 }
@@ -0,0 +1,126 @@
+defmodule Json.EncoderTest do
+  use ExUnit.Case, async: true
+
+  import Json
+  alias Json.{Error, Native}
+
+  test "literals" do
+    assert encode!(nil) == "null"
+    assert encode!(true) == "true"
+    assert encode!(false) == "false"
+    assert encode!(:poison) == ~s("poison")
+  end
+
+  test "null term" do
+    assert encode!([nil, :null], null_term: :null) == ~s(["nil",null])
+    assert encode!([nil, :null, :undefined], null_term: [nil, :undefined]) == ~s([null,"null",null])
+    assert encode!(%{"a" => :undefined}, null_term: [:undefined]) == ~s({"a":null})
+    assert encode!([nil], null_term: []) == ~s(["nil"])
+    assert encode!([true, false], null_term: [true]) == "[true,false]"
+
+    assert_raise ArgumentError, fn -> encode(nil, null_term: "null") end
+    assert_raise ArgumentError, fn -> encode(nil, null_term: [:null, "null"]) end
+  end
+
+  test "numbers" do
+    assert encode!(1) == "1"
+    assert encode!(-1) == "-1"
+    assert encode!(0.1) == "0.1"
+    assert encode!([1.0, 123.456, 1.0e20, 1.5e300, 1.0e-7]) == "[1,123.456,1e20,1.5e300,0.0000001]"
+    assert encode!(18_446_744_073_709_551_615) == "18446744073709551615"
+    assert encode!(18_446_744_073_709_551_616) == "18446744073709551616"
+    assert encode!(-9_223_372_036_854_775_809) == "-9223372036854775809"
+
+    big = String.to_integer("1" <> String.duplicate("0", 400)) + 1
+    assert encode!([big, -big]) == "[#{big},-#{big}]"
+  end
+
+  test "raw numbers" do
+    assert encode!({:json_number, "1.000"}) == "1.000"
+    assert encode!([{:json_number, "-0"}, {:json_number, "1E+400"}]) == "[-0,1E+400]"
+    assert encode!(%{"n" => {:json_number, "123456789012345678901234567890"}}) ==
+             ~s({"n":123456789012345678901234567890})
+
+    for text <- ["1,2", "01", "+1", ".5", "1.", "NaN", " 1", ""] do
+      assert encode({:json_number, text}) == {:error, {:invalid_number, {:json_number, text}}}
+    end
+
+    assert encode({:json_number, 1}) == {:error, {:unsupported_type, {:json_number, 1}}}
+    assert encode({:number, "1"}) == {:error, {:unsupported_type, {:number, "1"}}}
+  end
+
+  test "non-finite numbers" do
+    data = [:nan, :infinity, :negative_infinity]
+    assert encode!(data) == ~s(["nan","infinity","negative_infinity"])
+    assert encode!(data, non_finite: true) == "[NaN,Infinity,-Infinity]"
+    assert encode!(%{"x" => :inf}, non_finite: %{infinity: :inf}) == ~s({"x":Infinity})
+    assert encode!(:infinity, non_finite: %{infinity: :inf}) == ~s("infinity")
+
+    assert_raise ArgumentError, fn -> encode(:nan, non_finite: %{nan: "NaN"}) end
+    assert_raise ArgumentError, fn -> encode(:nan, unknown: true) end
+  end
+
+  test "strings" do
+    assert encode!("hello world") == ~s("hello world")
+    assert encode!("\"\\\n") == ~s("\\"\\\\\\n")
+    assert encode!("☃") == ~s("☃")
+    assert encode!("\u0000\u001f\b\f\r\t/\u007f") == ~s("\\u0000\\u001f\\b\\f\\r\\t/\u007f")
+  end
+
+  test "lists and maps" do
+    assert encode!([]) == "[]"
+    assert encode!([1, "two", [3]]) == ~s([1,"two",[3]])
+    assert encode!(%{}) == "{}"
+    assert encode!(%{"foo" => "bar", baz: [nil]}) == ~s({"baz":[null],"foo":"bar"})
+    assert encode!(%{:a => 1, :b => 2, "a" => 3}) == ~s({"a":3,"b":2})
+  end
+
+  test "ordered objects" do
+    assert encode!([{"b", 1}, {:a, 2}]) == ~s({"b":1,"a":2})
+    assert encode!([{"a", 1}, {"a", 2}]) == ~s({"a":1,"a":2})
+    assert encode!(a: [b: nil]) == ~s({"a":{"b":null}})
+    assert encode!({[{"b", 1}, {:a, {[]}}]}) == ~s({"b":1,"a":{}})
+    assert encode!({[]}) == "{}"
+    assert encode!([{:json_number, "1.0"}, {:json_number, "2"}]) == "[1.0,2]"
+    assert encode!([{:json_number, 1}]) == ~s({"json_number":1})
+
+    assert encode([{"a", 1}, 2]) == {:error, {:unsupported_type, 2}}
+    assert encode({[{"a", 1, 2}]}) == {:error, {:unsupported_type, {"a", 1, 2}}}
+    assert encode([{1, 2}]) == {:error, {:invalid_key, 1}}
+    assert encode({[1], [2]}) == {:error, {:unsupported_type, {[1], [2]}}}
+  end
+
+  test "deep nesting" do
+    depth = 100_000
+    lists = Enum.reduce(1..depth, [], fn _, acc -> [acc] end)
+    assert encode!(lists) == String.duplicate("[", depth + 1) <> String.duplicate("]", depth + 1)
+
+    maps = Enum.reduce(1..depth, 1, fn _, acc -> %{"a" => acc} end)
+    assert encode!(maps) == String.duplicate(~s({"a":), depth) <> "1" <> String.duplicate("}", depth)
+
+    members = Enum.reduce(1..depth, 1, fn _, acc -> {[{"a", [acc, 2]}]} end)
+    assert encode!(members) ==
+             String.duplicate(~s({"a":[), depth) <> "1" <> String.duplicate(",2]}", depth)
+
+    pid = self()
+    assert encode(Enum.reduce(1..depth, [pid], fn _, acc -> [acc] end)) ==
+             {:error, {:unsupported_type, pid}}
+  end
+
+  test "normal and dirty schedulers agree" do
+    data = %{"list" => [1, 2.5, "three", true, nil], "map" => %{"nested" => %{}}}
+    assert Native.encode(data, %{}) == Native.encode_dirty(data, %{})
+  end
+
+  test "unsupported terms" do
+    assert encode({:a, :b}) == {:error, {:unsupported_type, {:a, :b}}}
+    assert encode([self()]) == {:error, {:unsupported_type, self()}}
+
+    ref = make_ref()
+    assert encode(%{"ref" => ref}) == {:error, {:unsupported_type, ref}}
+    assert encode(<<128>>) == {:error, {:unsupported_type, <<128>>}}
+    assert encode(%{{1, 2} => 3}) == {:error, {:invalid_key, {1, 2}}}
+
+    assert_raise Error, "unsupported_type: {:a, :b}", fn -> encode!({:a, :b}) end
+  end
+end
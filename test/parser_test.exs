@@ -16,7 +16,7 @@ defmodule Json.ParserTest do
 
   test "large input" do
     data = File.read!(Path.expand("../bench/data/issue90.json", __DIR__))
-    {:ok, expected} = decode_naive(data)
+    {:ok, expected} = decode_naive(data, %{})
     assert expected == decode!(data)
   end
 
@@ -25,21 +25,335 @@ defmodule Json.ParserTest do
     {:ok, expected} = decode(data)
     {:ok, actual} = threaded_decode(data)
     assert actual == expected
+
+    assert decode_threaded("[1,", %{}) == :ok
+    error = %{reason: :unexpected_end, token: nil, position: 3, line: 1, column: 4}
+    assert_receive {:error, ^error}
+    assert threaded_decode("[1,") == {:error, error}
+  end
+
+  test "binary input" do
+    data = File.read!(Path.expand("../bench/data/issue90.json", __DIR__))
+    {:ok, expected} = decode_naive(data, %{})
+
+    # A sub-binary of a larger binary, parsed over many decode_iter calls.
+    <<_::binary-size(3), slice::binary-size(byte_size(data)), _::binary>> = "xyz" <> data <> "xyz"
+    assert parse(slice) == {:ok, expected}
+    assert threaded_decode(slice) == {:ok, expected}
+
+    for input <- [["[", "]"], :nope] do
+      assert_raise ArgumentError, fn -> decode_naive(input, %{}) end
+      assert_raise ArgumentError, fn -> decode_dirty(input, %{}) end
+      assert_raise ArgumentError, fn -> decode_threaded(input, %{}) end
+      assert_raise ArgumentError, fn -> decode_init(input, %{}) end
+    end
+  end
+
+  test "streams" do
+    data = File.read!(Path.expand("../bench/data/issue90.json", __DIR__))
+    {:ok, expected} = decode(data)
+
+    for size <- [1, 7, 4096, byte_size(data)] do
+      chunks = for <<chunk::binary-size(size) <- data>>, do: chunk
+      rest = binary_part(data, length(chunks) * size, rem(byte_size(data), size))
+      assert decode_stream(chunks ++ [rest]) == {:ok, expected}
+    end
+
+    stream = decode_stream_init(%{})
+    assert {:more, _} = decode_stream_feed(stream, ~s([1, "\\ud83d))
+    assert {:more, _} = decode_stream_feed(stream, ~s(\\ude00", tr))
+    assert {:more, _} = decode_stream_feed(stream, "ue, 12")
+    assert {:more, _} = decode_stream_feed(stream, "3]\n")
+    assert decode_stream_finish(stream) == {:ok, [1, "😀", true, 123]}
+    assert_raise ArgumentError, fn -> decode_stream_feed(stream, "[]") end
+    assert_raise ArgumentError, fn -> decode_stream_finish(stream) end
+
+    error = %{reason: :unexpected_token, token: "x", position: 9, line: 2, column: 6}
+    assert decode_stream(["[1,\n", "  2,", " x]"]) == {:error, error}
+    assert {:error, %{reason: :unexpected_end, position: 5}} = decode_stream(["[1, 2"])
+    assert {:error, %{reason: :unexpected_end, position: 0}} = decode_stream([])
+    assert {:error, %{reason: :unexpected_token, position: 4}} = decode_stream(["[]\n", " x"])
+
+    error = %{reason: :duplicate_key, token: "a", position: 11, line: 2, column: 3}
+    assert decode_stream([~s({"a": 1,\n  "), ~s(a": 2})], duplicate_keys: :reject) == {:error, error}
+    assert {:ok, %{a: 1}} = decode_stream([~s({"a"), ": 1}"], keys: :atoms)
+    assert {:error, %{reason: :too_many_elements}} = decode_stream(["[1,", "2]"], max_elements: 1)
+    assert {:error, %{reason: :input_too_large, position: 3}} = decode_stream(["[1,", "2]"], max_bytes: 3)
+
+    long = String.duplicate("x", 99_993)
+    data = ~s(["#{long}", 1])
+    chunks = for <<chunk::binary-size(1000) <- data>>, do: chunk
+    assert decode_stream(chunks) == {:ok, [long, 1]}
+    assert {:error, %{reason: :string_too_long}} = decode_stream(chunks, max_string_length: 5000)
+  end
+
+  test "json lines" do
+    data = ~s({"a": 1}\r\n\n  \n[2, "x"]\ntrue)
+    assert decode_lines(data) == {:ok, [%{"a" => 1}, [2, "x"], true]}
+    assert decode_lines(data <> "\n") == {:ok, [%{"a" => 1}, [2, "x"], true]}
+    assert decode_lines("") == {:ok, []}
+    assert decode_lines(~s(["a"]\n), keys: :atoms, copy_strings: true) == {:ok, [["a"]]}
+    assert {:ok, [%{a: 1}]} = decode_lines(~s({"a": 1}), keys: :atoms)
+
+    # A document may not span lines.
+    error = %{reason: :unexpected_end, token: nil, position: 12, line: 2, column: 4}
+    assert decode_lines(~s({"a": 1}\n[2,\n3]\n)) == {:error, error}
+
+    error = %{reason: :unexpected_token, token: "x", position: 14, line: 3, column: 11}
+    assert decode_lines(~s(1\n\n[1, {"é": x}]\n)) == {:error, error}
+    assert {:error, %{reason: :unexpected_token, line: 1}} = decode_lines("1 2")
+
+    results = data |> stream_lines() |> Enum.to_list()
+    assert results == [{:ok, %{"a" => 1}}, {:ok, [2, "x"]}, {:ok, true}]
+
+    results = ~s([1]\n[2,\n[3]) |> stream_lines() |> Enum.to_list()
+    assert [{:ok, [1]}, {:error, %{reason: :unexpected_end, line: 2}}, {:ok, [3]}] = results
+    assert ~s([1]\n[2]) |> stream_lines() |> Enum.take(1) == [{:ok, [1]}]
+  end
+
+  test "sequences" do
+    assert decode_sequence(~s({"a":1}{"b":2}\n[3] "x"true 4)) ==
+             {:ok, [%{"a" => 1}, %{"b" => 2}, [3], "x", true, 4]}
+
+    data = <<0x1E, ~s({"a": "b"}\n), 0x1E, 0x1E, "2\n", 0x1E, "\n">>
+    assert decode_sequence(data) == {:ok, [%{"a" => "b"}, 2]}
+    assert decode_sequence(data, keys: :atoms) == {:ok, [%{a: "b"}, 2]}
+    assert decode_sequence(" \n") == {:ok, []}
+
+    error = %{reason: :unexpected_token, token: "]", position: 8, line: 2, column: 3}
+    assert decode_sequence("[1] 2\n[]]") == {:error, error}
+    assert {:error, %{reason: :unexpected_token, position: 3}} = decode_sequence(<<"[1,", 0x1E, "2]">>)
+
+    # Numbers and literals only end at whitespace, a separator or the end.
+    assert {:error, %{reason: :unexpected_token, token: "1", position: 1}} = decode_sequence("01")
+    assert {:error, %{reason: :unexpected_token, token: "-", position: 2}} = decode_sequence("-0-0")
+    assert {:error, %{reason: :unexpected_token, token: "n", position: 4}} = decode_sequence("truenull")
+    assert decode_sequence(<<"0", 0x1E, "1">>) == {:ok, [0, 1]}
+
+    assert decode_next(data, 0) == {:ok, %{"a" => "b"}, 11}
+    assert decode_next(data, 11) == {:ok, 2, 15}
+    assert decode_next(data, 15) == :done
+    assert decode_next(data, byte_size(data)) == :done
+    assert {:error, %{reason: :unexpected_end, position: 4}} = decode_next("1 [2", 1)
+    assert_raise ArgumentError, fn -> decode_next("1", 2) end
+  end
+
+  test "lenient syntax" do
+    config = """
+    // Settings
+    {
+      name: 'it\\'s', /* unquoted keys */ $port: 0x1F90,
+      "ratios": [+1, .5, -0xff, 'x',],
+    }
+    """
+
+    expected = %{"name" => "it's", "$port" => 8080, "ratios" => [1, 0.5, -255, "x"]}
+    assert decode(config, lenient: true) == {:ok, expected}
+    assert parse(config, lenient: true) == {:ok, expected}
+    assert decode_stream(for(<<byte <- config>>, do: <<byte>>), lenient: true) == {:ok, expected}
+    assert decode(config, lenient: true, keys: :atoms) ==
+             {:ok, %{name: "it's", "$port": 8080, ratios: [1, 0.5, -255, "x"]}}
+
+    assert {:error, %{reason: :unexpected_token, token: "/", line: 1}} = decode(config)
+    assert {:error, %{reason: :unexpected_token, token: "}"}} = decode(~s({"a": 1,}))
+    assert decode(~s({"a": 1,}), lenient: true) == {:ok, %{"a" => 1}}
+    assert decode("[1] // no newline", lenient: true) == {:ok, [1]}
+
+    # Hex integers of any size, a signed Infinity and a `.` that isn't a number.
+    hex = "1" <> String.duplicate("0", 40)
+    value = String.to_integer(hex, 16)
+    assert decode("[0x#{hex}, -0x#{hex}]", lenient: true) == {:ok, [value, -value]}
+    assert decode("[+Infinity]", lenient: true, non_finite: true) == {:ok, [:infinity]}
+    assert decode_stream(["[+Inf", "inity]"], lenient: true, non_finite: true) == {:ok, [:infinity]}
+    assert {:error, %{reason: :non_finite_number, token: "+Infinity"}} = decode("+Infinity", lenient: true)
+    error = %{reason: :invalid_number, token: ".", position: 1, line: 1, column: 2}
+    assert decode("[.]", lenient: true) == {:error, error}
+    assert {:error, %{reason: :invalid_number, token: ".", position: 0}} = decode(".", lenient: true)
+
+    # Lines holding only comments are blank.
+    lines = "// header\n1\n  /* none */ \n[2] // two\n"
+    assert decode_lines(lines, lenient: true) == {:ok, [1, [2]]}
+    assert lines |> stream_lines(lenient: true) |> Enum.to_list() == [{:ok, 1}, {:ok, [2]}]
+    assert {:error, %{reason: :unexpected_token, line: 1}} = decode_lines(lines)
+
+    error = %{reason: :unexpected_end, token: nil, position: 4, line: 1, column: 5}
+    assert decode("[1, /* ]", lenient: true) == {:error, error}
+    assert {:error, %{reason: :unexpected_token, token: ","}} = decode("[1,,]", lenient: true)
+    assert {:error, %{reason: :invalid_number, token: "0x"}} = decode("0x", lenient: true)
+  end
+
+  test "decimal floats" do
+    decimal = fn sign, coef, exp -> %{__struct__: Decimal, sign: sign, coef: coef, exp: exp} end
+
+    input = "[19.990000000000001, -1.50, 0.0, 1e3, 12.5E-3, 7, 123456789012345678901234567890.1]"
+
+    expected = [
+      decimal.(1, 19_990_000_000_000_001, -15),
+      decimal.(-1, 150, -2),
+      decimal.(1, 0, -1),
+      decimal.(1, 1, 3),
+      decimal.(1, 125, -4),
+      7,
+      decimal.(1, 1_234_567_890_123_456_789_012_345_678_901, -1)
+    ]
+
+    assert decode(input, floats: :decimals) == {:ok, expected}
+    assert parse(input, floats: :decimals) == {:ok, expected}
+    assert decode_stream([input], floats: :decimals) == {:ok, expected}
+    assert decode(input, floats: :native) == decode(input)
+    assert decode(~s({"a": +.5}), floats: :decimals, lenient: true) == {:ok, %{"a" => decimal.(1, 5, -1)}}
+  end
+
+  test "raw numbers" do
+    input = ~s({"a": [1.000, -0, 1E+2, 123456789012345678901234567890, 0.1e-7]})
+
+    expected = %{
+      "a" => [
+        {:json_number, "1.000"},
+        {:json_number, "-0"},
+        {:json_number, "1E+2"},
+        {:json_number, "123456789012345678901234567890"},
+        {:json_number, "0.1e-7"}
+      ]
+    }
+
+    assert decode(input, raw_numbers: true) == {:ok, expected}
+    assert parse(input, raw_numbers: true) == {:ok, expected}
+    chunks = for <<chunk::binary-size(1) <- input>>, do: chunk
+    assert decode_stream(chunks, raw_numbers: true) == {:ok, expected}
+    assert decode(input, raw_numbers: true, floats: :decimals) == {:ok, expected}
+    assert decode(input, raw_numbers: false) == decode(input)
+    assert decode("[+1, 0x1F]", raw_numbers: true, lenient: true) ==
+             {:ok, [{:json_number, "+1"}, {:json_number, "0x1F"}]}
+
+    assert input |> decode!(raw_numbers: true) |> encode!() ==
+             ~s({"a":[1.000,-0,1E+2,123456789012345678901234567890,0.1e-7]})
+  end
+
+  test "non-finite numbers" do
+    input = "[NaN, Infinity, -Infinity, 1.5]"
+    expected = [:nan, :infinity, :negative_infinity, 1.5]
+    assert decode(input, non_finite: true) == {:ok, expected}
+    assert parse(input, non_finite: true) == {:ok, expected}
+    assert decode_stream(["[Na", "N, -Inf", "inity]"], non_finite: true) ==
+             {:ok, [:nan, :negative_infinity]}
+    assert decode(input, non_finite: %{nan: :undefined, infinity: :inf}) ==
+             {:ok, [:undefined, :inf, :negative_infinity, 1.5]}
+    assert decode(input, non_finite: false) == decode(input)
+
+    error = %{reason: :non_finite_number, token: "NaN", position: 1, line: 1, column: 2}
+    assert decode(input) == {:error, error}
+
+    assert_raise Error, "Non-finite number -Infinity in JSON at position 0", fn ->
+      decode!("-Infinity")
+    end
+
+    assert input |> decode!(non_finite: true) |> encode!(non_finite: true) ==
+             "[NaN,Infinity,-Infinity,1.5]"
+
+    # Floats past the range of a double.
+    error = %{reason: :invalid_number, token: "1e400", position: 0, line: 1, column: 1}
+    assert decode("1e400") == {:error, error}
+    error = %{reason: :invalid_number, token: "-1e400", position: 1, line: 1, column: 2}
+    assert decode("[-1e400]") == {:error, error}
+    assert parse("[-1e400]") == {:error, error}
+    assert {:error, %{reason: :invalid_number, token: "1e309", position: 4}} =
+             decode("[1, 1e309]", integral_floats: true)
+    assert {:error, %{reason: :invalid_number, token: "-1e400", position: 6, line: 2, column: 3}} =
+             decode_stream(["[1,\n", "  -1e4", "00]"])
+    assert decode("[1e400, -1e400]", non_finite: true) == {:ok, [:infinity, :negative_infinity]}
+    assert decode_stream(["[1e4", "00]"], non_finite: %{infinity: :inf}) == {:ok, [:inf]}
+    assert {:ok, %{__struct__: Decimal}} = decode("1e400", floats: :decimals)
+  end
+
+  test "ordered objects" do
+    input = ~s({"z": 1, "a": {"y": [{}], "b": 2}, "m": 3})
+    proplist = [{"z", 1}, {"a", [{"y", [[]]}, {"b", 2}]}, {"m", 3}]
+    ejson = {[{"z", 1}, {"a", {[{"y", [{[]}]}, {"b", 2}]}}, {"m", 3}]}
+
+    for {objects, expected} <- [proplists: proplist, ejson: ejson] do
+      assert decode_naive(input, %{objects: objects}) == {:ok, expected}
+      assert decode_dirty(input, %{objects: objects}) == {:ok, expected}
+      assert threaded_decode(input, objects: objects) == {:ok, expected}
+      assert parse(input, objects: objects) == {:ok, expected}
+      assert decode_stream([~s({"z": 1, "a": {"y), ~s(": [{}], "b": 2}, "m": 3})], objects: objects) ==
+               {:ok, expected}
+      assert decode_lines(input <> "\n" <> input, objects: objects) == {:ok, [expected, expected]}
+      assert decode_sequence(input <> input, objects: objects) == {:ok, [expected, expected]}
+    end
+
+    assert decode(input, objects: :maps) == decode(input)
+
+    # Only ejson tells an empty object from an empty array.
+    assert decode("[{}, []]", objects: :proplists) == {:ok, [[], []]}
+    assert decode("[{}, []]", objects: :ejson) == {:ok, [{[]}, []]}
+    assert "{}" |> decode!(objects: :proplists) |> encode!() == "[]"
+    assert "{}" |> decode!(objects: :ejson) |> encode!() == "{}"
+    assert decode(~s({"b": 1, "a": 2}), objects: :proplists, keys: :atoms) == {:ok, [b: 1, a: 2]}
+
+    input = ~s({"a": 1, "b": 2, "a": 3})
+    assert decode(input, objects: :proplists) == {:ok, [{"a", 3}, {"b", 2}]}
+    assert decode(input, objects: :proplists, duplicate_keys: :first) == {:ok, [{"a", 1}, {"b", 2}]}
+    assert {:error, %{reason: :duplicate_key, token: "a", position: 17}} =
+             decode(input, objects: :ejson, duplicate_keys: :reject)
+
+    for objects <- [:proplists, :ejson] do
+      input = ~s({"z":1,"a":{"y":[{"x":null}],"b":2},"m":3})
+      assert input |> decode!(objects: objects) |> encode!() == input
+    end
+  end
+
+  test "null term" do
+    input = ~s({"a": null, "b": [null, "null"]})
+    expected = %{"a" => :undefined, "b" => [:undefined, "null"]}
+
+    assert decode_naive(input, %{null_term: :undefined}) == {:ok, expected}
+    assert decode_dirty(input, %{null_term: :undefined}) == {:ok, expected}
+    assert threaded_decode(input, null_term: :undefined) == {:ok, expected}
+    assert parse(input, null_term: :undefined) == {:ok, expected}
+    assert decode_stream([~s({"a": nu), ~s(ll, "b": [null, "null"]})], null_term: :undefined) ==
+             {:ok, expected}
+    assert decode_lines("null\n[null]\n", null_term: :null) == {:ok, [:null, [:null]]}
+    assert decode_sequence("null null", null_term: :null) == {:ok, [:null, :null]}
+    assert decode(input, null_term: nil) == decode(input)
+
+    assert input |> decode!(null_term: :null) |> encode!(null_term: :null) ==
+             ~s({"a":null,"b":[null,"null"]})
+  end
+
+  test "sub-binary strings" do
+    data = ~s({"key": ["plain", "esc\\naped"]}) <> String.duplicate(" ", 100)
+
+    assert {:ok, %{"key" => [plain, escaped]}} = decode(data)
+    assert plain == "plain"
+    assert :binary.referenced_byte_size(plain) == byte_size(data)
+    assert :binary.referenced_byte_size(escaped) == byte_size("esc\naped")
+
+    for {:ok, %{"key" => [plain, _]}} <- [
+          decode(data, copy_strings: true),
+          parse(data, copy_strings: true),
+          threaded_decode(data, copy_strings: true)
+        ] do
+      assert :binary.referenced_byte_size(plain) == byte_size("plain")
+    end
+
+    assert {:ok, %{"key" => [plain, _]}} = parse(data)
+    assert :binary.referenced_byte_size(plain) == byte_size(data)
   end
 
   test "numbers" do
-    assert_raise Error, "Unexpected end of JSON", fn -> decode!("-") end
-    assert_raise Error, "Unexpected character: - at (1:2)", fn -> decode!("--1") end
-    assert_raise Error, "Unexpected character: 1 at (1:2)", fn -> decode!("01") end
-    assert_raise Error, "Unexpected character: . at (1:1)", fn -> decode!(".1") end
-    assert_raise Error, "Unexpected end of JSON", fn -> decode!("1.") end
-    # FIXME: should be "Unexpected end of JSON input"
-    assert_raise Error, "Unexpected end of JSON", fn -> decode!("1e") end
-    # FIXME: should be "Unexpected end of JSON input"
-    assert_raise Error, "Unexpected end of JSON", fn -> decode!("1.0e+") end
+    assert_raise Error, "Unexpected number - in JSON at position 0", fn -> decode!("-") end
+    assert_raise Error, "Unexpected number --1 in JSON at position 0", fn -> decode!("--1") end
+    assert_raise Error, "Unexpected token 1 in JSON at position 1", fn -> decode!("01") end
+    assert_raise Error, "Unexpected token . in JSON at position 0", fn -> decode!(".1") end
+    assert_raise Error, "Unexpected end of JSON input at position 2", fn -> decode!("1.") end
+    assert_raise Error, "Unexpected token ] in JSON at position 3", fn -> decode!("[1.]") end
+    assert_raise Error, "Unexpected end of JSON input at position 2", fn -> decode!("1e") end
+    assert_raise Error, "Unexpected end of JSON input at position 5", fn -> decode!("1.0e+") end
+    assert_raise Error, "Unexpected token . in JSON at position 3", fn -> decode!("1.5.3") end
+    assert_raise Error, "Unexpected number -.5 in JSON at position 0", fn -> decode!("-.5") end
 
     assert decode!("0") == 0
-    assert decode!("01") == 01
     assert decode!("1") == 1
     assert decode!("-0") == 0
     assert decode!("-1") == -1
@@ -59,17 +373,77 @@ defmodule Json.ParserTest do
     assert decode!("123456789.123456789e123") == 123_456_789.123456789e123
   end
 
+  test "number classification" do
+    cases = [
+      {"1", 1, 1},
+      {"-0", 0, 0},
+      {"1.0", 1.0, 1},
+      {"-0.0", -0.0, 0},
+      {"1e2", 100.0, 100},
+      {"1E+2", 100.0, 100},
+      {"1500e-2", 15.0, 15},
+      {"1.5", 1.5, 1.5},
+      {"1e-2", 0.01, 0.01},
+      {"1.0e20", 1.0e20, 100_000_000_000_000_000_000},
+      {"9223372036854775808.0", 9_223_372_036_854_775_808.0, 9_223_372_036_854_775_808}
+    ]
+
+    decoders = [
+      fn data, opts -> decode_naive(data, opts) end,
+      fn data, opts -> decode_dirty(data, opts) end,
+      fn data, opts -> threaded_decode(data, opts) end,
+      fn data, opts -> parse(data, opts) end,
+      fn data, opts -> decode_stream([data], opts) end
+    ]
+
+    for {text, lexical, integral} <- cases, decoder <- decoders do
+      assert {:ok, [actual]} = decoder.("[#{text}]", %{})
+      assert actual === lexical, "#{text} decoded to #{inspect(actual)}"
+
+      assert {:ok, [actual]} = decoder.("[#{text}]", %{integral_floats: true})
+      assert actual === integral, "#{text} decoded to #{inspect(actual)}"
+    end
+
+    decimal = %{__struct__: Decimal, sign: 1, coef: 15, exp: -1}
+    assert decode("[1.0e1, 1.5]", floats: :decimals, integral_floats: true) == {:ok, [10, decimal]}
+    assert decode("[1.0]", raw_numbers: true, integral_floats: true) == {:ok, [{:json_number, "1.0"}]}
+  end
+
+  test "big integers" do
+    assert decode!("9223372036854775807") == 9_223_372_036_854_775_807
+    assert decode!("9223372036854775808") == 9_223_372_036_854_775_808
+    assert decode!("-9223372036854775808") == -9_223_372_036_854_775_808
+    assert decode!("-9223372036854775809") == -9_223_372_036_854_775_809
+    assert decode!("18446744073709551615") == 18_446_744_073_709_551_615
+    assert decode!("123456789012345678901234567890") == 123_456_789_012_345_678_901_234_567_890
+    assert decode!("-123456789012345678901234567890") == -123_456_789_012_345_678_901_234_567_890
+
+    digits = String.duplicate("9", 700)
+    assert decode!(digits) == String.to_integer(digits)
+    assert parse!("[#{digits}]") == [String.to_integer(digits)]
+
+    assert {:ok, [18_446_744_073_709_551_616]} = threaded_decode("[18446744073709551616]")
+    assert {:ok, [18_446_744_073_709_551_616]} = decode_dirty("[18446744073709551616]", %{})
+
+    input = ~s({"id":18446744073709551616,"n":[-9223372036854775809,#{digits}]})
+    assert input |> decode!() |> encode!() == input
+  end
+
   test "strings" do
-    assert_raise Error, "Invalid or unexpected token at position 1", fn -> decode!(~s(")) end
-    assert_raise Error, "Invalid or unexpected token at position 3", fn -> decode!(~s("\\")) end
+    assert_raise Error, "Unexpected end of JSON input at position 1", fn -> decode!(~s(")) end
+    assert_raise Error, "Unexpected end of JSON input at position 3", fn -> decode!(~s("\\")) end
     assert_raise Error, "Unexpected token k in JSON at position 2", fn -> decode!(~s("\\k")) end
-    # FIXME assert_raise Error, "Unexpected end of JSON", fn -> decode!(<<34, 128, 34>>) end
-    # FIXME assert_raise Error, "Unexpected end of JSON", fn -> decode!(~s("\\u2603\\")) end
-    assert_raise Error, "Invalid or unexpected token at position 1", fn ->
+    assert_raise Error, "Invalid UTF-8 in JSON at position 1", fn -> decode!(<<34, 128, 34>>) end
+    assert_raise Error, "Unexpected end of JSON input at position 9", fn ->
+      decode!(~s("\\u2603\\"))
+    end
+
+    assert_raise Error, "Unexpected end of JSON input at position 41", fn ->
       decode!(~s("Here's a snowman for you: ☃. Good day!))
     end
 
-    assert_raise Error, "Invalid or unexpected token at position 1", fn -> decode!(~s("𝄞)) end
+    assert_raise Error, "Unexpected end of JSON input at position 5", fn -> decode!(~s("𝄞)) end
+    assert_raise Error, "Unexpected control character in JSON at position 2", fn -> decode!(~s("a\n")) end
 
     assert decode!(~s("\\"\\\\\\/\\b\\f\\n\\r\\t")) == ~s("\\/\b\f\n\r\t)
     assert decode!(~s("\\u2603")) == "☃"
@@ -80,11 +454,66 @@ defmodule Json.ParserTest do
     assert decode!(~s("✔︎")) == "✔︎"
   end
 
+  test "invalid utf-8" do
+    error = %{reason: :invalid_utf8, token: nil, position: 4, line: 2, column: 3}
+    data = <<"[\n\"a", 0xE2, 0x98, "\"]">>
+    assert decode_naive(data, %{}) == {:error, error}
+    assert decode_dirty(data, %{}) == {:error, error}
+    assert threaded_decode(data) == {:error, error}
+    assert parse(data) == {:error, error}
+
+    assert {:ok, ["a\uFFFD"]} = decode(data, invalid_utf8: :replace)
+    assert {:ok, %{"\uFFFD\uFFFDb" => 1}} = parse(<<"{\"", 0xC0, 0xAF, "b\": 1}">>, invalid_utf8: :replace)
+    assert {:ok, ["été"]} = decode(~s(["été"]))
+
+    # Outside strings the bytes are simply unexpected.
+    assert {:error, %{reason: :unexpected_token, position: 1}} = decode(<<"[", 255, "]">>)
+  end
+
+  test "unicode escapes" do
+    assert parse!(~s("\\u2603")) == "☃"
+    assert parse!(~s("\\u00e9t\\u00E9")) == "été"
+    assert parse!(~s("\\u2028\\u2029")) == "\u2028\u2029"
+    assert parse!(~s("\\uD834\\uDD1E")) == "𝄞"
+    assert parse!(~s("\\uD799\\uD799")) == "힙힙"
+    assert parse!(~s({"\\u0041": ["\\u0000"]})) == %{"A" => [<<0>>]}
+
+    assert_raise Error, "Unexpected end of JSON input at position 9", fn ->
+      parse!(~s("\\u2603\\"))
+    end
+
+    assert_raise Error, "Unexpected end of JSON input at position 5", fn -> parse!(~s("\\u26)) end
+    assert_raise Error, "Bad Unicode escape in JSON at position 5", fn -> parse!(~s("\\u26x3")) end
+  end
+
+  test "lone surrogates" do
+    assert_raise Error, "Lone surrogate \\uD834 in JSON at position 1", fn ->
+      parse!(~s("\\uD834"))
+    end
+
+    assert_raise Error, "Lone surrogate \\uDD1E in JSON at position 2", fn ->
+      parse!(~s("a\\uDD1E"))
+    end
+
+    assert_raise Error, "Lone surrogate \\uD834 in JSON at position 1", fn ->
+      parse!(~s("\\uD834\\u0041"))
+    end
+
+    assert parse!(~s("\\uD834"), lone_surrogates: :replace) == "\uFFFD"
+    assert parse!(~s("\\uD834\\u0041"), lone_surrogates: :replace) == "\uFFFDA"
+    assert parse!(~s("\\uDD1E\\uD834"), lone_surrogates: :replace) == "\uFFFD\uFFFD"
+
+    assert parse!(~s("\\uD834"), lone_surrogates: :wtf8) == <<0xED, 0xA0, 0xB4>>
+    assert parse!(~s("\\uDD1Ex"), lone_surrogates: :wtf8) == <<0xED, 0xB4, 0x9E, ?x>>
+    assert parse!(~s("\\uD834\\uDD1E"), lone_surrogates: :wtf8) == "𝄞"
+  end
+
   test "objects" do
     assert_raise Error, ~r"Unexpected end of JSON input", fn -> decode!("{") end
-    assert_raise Error, ~r"Unexpected end of JSON input", fn -> decode!("{,") end
+    assert_raise Error, ~r"Unexpected token , in JSON", fn -> decode!("{,") end
     assert_raise Error, ~r"Unexpected token } in JSON", fn -> decode!(~s({"foo"})) end
-    assert_raise Error, ~r"Unexpected end of JSON input", fn -> decode!(~s({"foo": "bar",})) end
+    assert_raise Error, ~r"Unexpected end of JSON input", fn -> decode!(~s({"foo")) end
+    assert_raise Error, ~r"Unexpected token } in JSON", fn -> decode!(~s({"foo": "bar",})) end
 
     assert decode!("{}") == %{}
     assert decode!(~s({"foo": "bar"})) == %{"foo" => "bar"}
@@ -98,8 +527,8 @@ defmodule Json.ParserTest do
 
   test "arrays" do
     assert_raise Error, ~r"Unexpected end of JSON", fn -> decode!("[") end
-    assert_raise Error, "Unexpected token , at position 1", fn -> decode!("[,") end
-    # FIXME assert_raise Error, "Unexpected token ] at position 5", fn -> decode!("[1,]") end
+    assert_raise Error, "Unexpected token , in JSON at position 1", fn -> decode!("[,") end
+    assert_raise Error, "Unexpected token ] in JSON at position 3", fn -> decode!("[1,]") end
 
     assert decode!("[]") == []
     assert decode!("[1, 2, 3]") == [1, 2, 3]
@@ -120,11 +549,198 @@ defmodule Json.ParserTest do
     assert decode!(~s(  {  "foo"  :  "bar"  ,  "baz"  :  "quux"  }  )) == expected
   end
 
-  test "atom keys"
-  # hash = :erlang.phash2(:crypto.strong_rand_bytes(8))
-  # assert_raise ArgumentError, fn -> decode!(~s({"key#{hash}": null}), keys: :atoms!) end
+  test "options are honored by every entry point" do
+    data = ~s(["\\uD834", {"\\uDD1E": 1}])
+    opts = %{lone_surrogates: :replace}
+    expected = ["\uFFFD", %{"\uFFFD" => 1}]
+
+    assert decode_naive(data, opts) == {:ok, expected}
+    assert decode_dirty(data, opts) == {:ok, expected}
+    assert threaded_decode(data, opts) == {:ok, expected}
+    assert parse(data, opts) == {:ok, expected}
+
+    error = %{reason: :lone_surrogate, token: "\\uD834", position: 1, line: 1, column: 2}
+    assert decode_naive(data, %{}) == {:error, error}
+    assert decode_dirty(data, %{}) == {:error, error}
+    assert threaded_decode(data) == {:error, error}
+    assert parse(data) == {:error, error}
+  end
+
+  test "error details" do
+    data = ~s({\n  "a": [1, 2],\n  "b": {"c" 3}\n})
+    error = %{reason: :unexpected_token, token: "3", position: 29, line: 3, column: 13}
 
-  # assert decode!(~s({"foo": "bar"}), keys: :atoms) == %{foo: "bar"}
-  # assert decode!(~s({"foo": "bar"}), keys: :atoms!) == %{foo: "bar"}
-  # end
+    assert decode_naive(data, %{}) == {:error, error}
+    assert decode_dirty(data, %{}) == {:error, error}
+    assert threaded_decode(data) == {:error, error}
+    assert parse(data) == {:error, error}
+
+    assert decode("[1, 2") ==
+             {:error, %{reason: :unexpected_end, token: nil, position: 5, line: 1, column: 6}}
+
+    assert decode(~s(["éé" 1]\n)) ==
+             {:error, %{reason: :unexpected_token, token: "1", position: 8, line: 1, column: 7}}
+
+    assert decode("[-]") ==
+             {:error, %{reason: :invalid_number, token: "-", position: 1, line: 1, column: 2}}
+
+    assert decode("[1] x") ==
+             {:error, %{reason: :unexpected_token, token: "x", position: 4, line: 1, column: 5}}
+
+    error = assert_raise Error, fn -> decode!("{\n}}") end
+    assert %Error{reason: :unexpected_token, token: "}", position: 3, line: 2, column: 2} = error
+  end
+
+  test "missing object values" do
+    error = %{reason: :unexpected_token, token: "}", position: 5, line: 1, column: 6}
+
+    for data <- [~s({"a":}), ~s({"a":}]), ~s({"a":}, "b": 1})] do
+      assert decode_naive(data, %{}) == {:error, error}
+      assert decode_dirty(data, %{}) == {:error, error}
+      assert threaded_decode(data) == {:error, error}
+      assert parse(data) == {:error, error}
+      assert decode_stream([data]) == {:error, error}
+    end
+
+    assert decode(~s([{"a":}])) == {:error, %{error | position: 6, column: 7}}
+    assert decode_stream([~s({"a":), "}"]) == {:error, error}
+    assert decode_lines(~s([1]\n{"a":}\n)) == {:error, %{error | position: 9, line: 2}}
+    assert decode_sequence(~s([1] {"a":})) == {:error, %{error | position: 9, column: 10}}
+  end
+
+  test "max depth" do
+    nested = fn depth, open, close ->
+      String.duplicate(open, depth) <> String.duplicate(close, depth)
+    end
+
+    assert {:ok, _} = decode(nested.(1024, "[", "]"))
+    assert {:ok, _} =
+             decode(String.duplicate(~s({"a":), 1023) <> "[]" <> String.duplicate("}", 1023))
+
+    error = %{reason: :max_depth_exceeded, token: "[", position: 1024, line: 1, column: 1025}
+    data = nested.(1025, "[", "]")
+    assert decode_naive(data, %{}) == {:error, error}
+    assert decode_dirty(data, %{}) == {:error, error}
+    assert threaded_decode(data) == {:error, error}
+    assert parse(data) == {:error, error}
+
+    assert {:ok, [[[]]]} = decode("[[[]]]", max_depth: 3)
+    assert {:ok, [%{"a" => %{}}]} = decode(~s([{"a": {}}]), max_depth: 3)
+    assert {:error, %{reason: :max_depth_exceeded, position: 3}} = decode("[[[[]]]]", max_depth: 3)
+    assert {:error, %{reason: :max_depth_exceeded, token: "{"}} = parse(~s([{"a": [{}]}]), max_depth: 3)
+
+    # Deep enough to overflow a recursive decoder's native stack.
+    assert {:error, %{reason: :max_depth_exceeded}} = decode(nested.(1_000_000, "[", "]"))
+
+    assert_raise Error, "Maximum nesting depth exceeded at position 1", fn ->
+      decode!("[[]]", max_depth: 1)
+    end
+  end
+
+  test "limits" do
+    assert {:ok, [12]} = decode("[12]", max_bytes: 4)
+    error = %{reason: :input_too_large, token: nil, position: 4, line: 1, column: 5}
+    assert decode_naive("[123]", %{max_bytes: 4}) == {:error, error}
+    assert decode_dirty("[123]", %{max_bytes: 4}) == {:error, error}
+    assert threaded_decode("[123]", max_bytes: 4) == {:error, error}
+    assert parse("[123]", max_bytes: 4) == {:error, error}
+
+    # The limit is on the whole input, not each line.
+    assert decode_lines("1\n2", max_bytes: 3) == {:ok, [1, 2]}
+    error = %{reason: :input_too_large, token: nil, position: 1, line: 1, column: 2}
+    assert decode_lines("1\n2", max_bytes: 1) == {:error, error}
+    error = %{error | position: 2, line: 2, column: 1}
+    assert decode_lines("1\n2\n", max_bytes: 2) == {:error, error}
+    assert "1\n2\n" |> stream_lines(max_bytes: 2) |> Enum.to_list() == [{:error, error}]
+
+    assert {:ok, %{"ab" => "é"}} = decode(~s({"ab": "\\u00e9"}), max_string_length: 2)
+    assert {:error, %{reason: :string_too_long, position: 1}} =
+             decode(~s(["abc"]), max_string_length: 2)
+    assert {:error, %{reason: :string_too_long, position: 1}} =
+             parse(~s({"abc": 1}), max_string_length: 2)
+
+    assert {:ok, [[1, 2], %{"a" => 1, "b" => 2}]} =
+             decode(~s([[1, 2], {"a": 1, "b": 2}]), max_elements: 2)
+    assert {:error, %{reason: :too_many_elements, position: 7}} =
+             decode("[1, 2, 3]", max_elements: 2)
+    assert {:error, %{reason: :too_many_elements, position: 17}} =
+             parse(~s({"a": 1, "b": 2, "c": 3}), max_elements: 2)
+
+    assert {:ok, [-123, 15.0]} = decode("[-123, 1.5e1]", max_number_digits: 3)
+    assert {:error, %{reason: :number_too_long, token: nil, position: 4}} =
+             decode("[1, -1234]", max_number_digits: 3)
+
+    assert {:ok, _} = decode("[[[[]]]]", max_depth: :infinity, max_elements: :infinity)
+
+    assert_raise Error, "Too many elements in JSON at position 4", fn ->
+      decode!("[1, 2]", max_elements: 1)
+    end
+  end
+
+  test "duplicate keys" do
+    data = ~s({"a": 1, "b": {"a": 2}, "a": 3})
+    assert decode!(data) == %{"a" => 3, "b" => %{"a" => 2}}
+    assert decode!(data, duplicate_keys: :last) == %{"a" => 3, "b" => %{"a" => 2}}
+    assert decode!(data, duplicate_keys: :first) == %{"a" => 1, "b" => %{"a" => 2}}
+    assert decode!(~s({"a": 1, "a": 2}), keys: :atoms, duplicate_keys: :first) == %{a: 1}
+
+    error = %{reason: :duplicate_key, token: "a", position: 24, line: 1, column: 25}
+    assert decode_naive(data, %{duplicate_keys: :reject}) == {:error, error}
+    assert decode_dirty(data, %{duplicate_keys: :reject}) == {:error, error}
+    assert threaded_decode(data, duplicate_keys: :reject) == {:error, error}
+    assert parse(data, duplicate_keys: :reject) == {:error, error}
+    assert {:ok, _} = decode(~s({"a": {"a": 1}, "b": [{"a": 2}]}), duplicate_keys: :reject)
+
+    assert_raise Error, ~s(Duplicate key "a" in JSON at position 9), fn ->
+      decode!(~s({"a": 1, "a": 2}), duplicate_keys: :reject)
+    end
+  end
+
+  test "invalid options" do
+    assert_raise ArgumentError, fn -> decode_naive("[]", %{unknown: true}) end
+    assert_raise ArgumentError, fn -> decode_dirty("[]", %{lone_surrogates: :ignore}) end
+    assert_raise ArgumentError, fn -> decode_naive("[]", %{max_bytes: -1}) end
+    assert_raise ArgumentError, fn -> decode_dirty("[]", %{max_elements: :none}) end
+    assert_raise ArgumentError, fn -> decode_naive("[]", %{duplicate_keys: :merge}) end
+    assert_raise ArgumentError, fn -> decode_naive("[]", %{copy_strings: :yes}) end
+    assert_raise ArgumentError, fn -> decode_naive("[]", %{invalid_utf8: :wtf8}) end
+    assert_raise ArgumentError, fn -> decode_naive("[]", %{lenient: :json5}) end
+    assert_raise ArgumentError, fn -> decode_naive("[]", %{floats: :decimal}) end
+    assert_raise ArgumentError, fn -> decode_naive("[]", %{raw_numbers: :yes}) end
+    assert_raise ArgumentError, fn -> decode_naive("[]", %{integral_floats: :yes}) end
+    assert_raise ArgumentError, fn -> decode_naive("[]", %{null_term: "null"}) end
+    assert_raise ArgumentError, fn -> decode_naive("[]", %{objects: :lists}) end
+    assert_raise ArgumentError, fn -> decode_naive("[]", %{null_term: [:null]}) end
+    assert_raise ArgumentError, fn -> decode_naive("[]", %{non_finite: %{nan: "NaN"}}) end
+    assert_raise ArgumentError, fn -> decode_naive("[]", %{non_finite: %{zero: :zero}}) end
+    assert_raise ArgumentError, fn -> decode_threaded("[]", %{"lone_surrogates" => :replace}) end
+    assert_raise ArgumentError, fn -> parse("[]", lone_surrogates: "replace") end
+    assert_raise ArgumentError, fn -> decode_naive("[]", %{max_depth: -1}) end
+  end
+
+  test "atom keys" do
+    hash = :erlang.phash2(:crypto.strong_rand_bytes(8))
+
+    assert_raise Error, ~s(Key "key#{hash}" is not an existing atom at position 1), fn ->
+      decode!(~s({"key#{hash}": null}), keys: :existing_atoms)
+    end
+
+    assert_raise Error, ~s(Key "key#{hash}" is not an existing atom at position 12), fn ->
+      parse!(~s({"foo": 1, "key#{hash}": null}), keys: :existing_atoms)
+    end
+
+    assert decode!(~s({"foo": "bar"}), keys: :atoms) == %{foo: "bar"}
+    assert decode!(~s({"foo": "bar"}), keys: :existing_atoms) == %{foo: "bar"}
+    assert decode!(~s({"foo": "bar"}), keys: :strings) == %{"foo" => "bar"}
+    assert parse!(~s({"foo": {"bar": [{"baz": 1}]}}), keys: :atoms) == %{foo: %{bar: [%{baz: 1}]}}
+
+    assert decode!(~s({"été": 1}), keys: :atoms) == %{été: 1}
+    assert decode!(~s({"\\u00e9t\\u00e9": 1}), keys: :existing_atoms) == %{été: 1}
+
+    long = String.duplicate("a", 256)
+
+    assert_raise Error, ~s(Key "#{long}" cannot be an atom at position 1), fn ->
+      decode!(~s({"#{long}": 1}), keys: :atoms)
+    end
+  end
 end